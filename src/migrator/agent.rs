@@ -0,0 +1,255 @@
+// Control surface for `MigMode::AGENT`: a small HTTP API served over a Unix
+// domain socket so orchestration tooling can monitor and drive a migration
+// without scraping logs.
+//
+// Requests are parsed by hand - this mirrors the lightweight approach taken
+// by `common::http_client` for outbound requests, just on the server side
+// this time. Only the request line (method and path) is parsed: the header
+// block is read up to its terminating blank line and then ignored, so
+// `Content-Length` is not looked at and no request body is read.
+
+use log::{debug, error, info};
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::migrator::common::config::Config;
+use crate::migrator::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "migrator::agent";
+
+/// Snapshot of the bits of `Config` worth exposing over `GET /config` -
+/// `Config` itself only derives `Deserialize` (it's read from the migrate
+/// config file, never serialized back out), so this is a small, purpose-built
+/// view rather than trying to make the whole config tree serializable.
+#[derive(Debug, Serialize)]
+pub(crate) struct ConfigSummary {
+    pub mode: String,
+    pub work_dir: String,
+    pub app_name: Option<String>,
+    pub check_vpn: bool,
+    pub check_api: bool,
+    pub check_timeout: u64,
+}
+
+impl From<&Config> for ConfigSummary {
+    fn from(config: &Config) -> ConfigSummary {
+        ConfigSummary {
+            mode: format!("{:?}", config.migrate.get_mig_mode()),
+            work_dir: config.migrate.get_work_dir().display().to_string(),
+            app_name: config.balena.get_app_name().map(String::from),
+            check_vpn: config.balena.is_check_vpn(),
+            check_api: config.balena.is_check_api(),
+            check_timeout: config.balena.get_check_timeout(),
+        }
+    }
+}
+
+/// Stage a migration has reached, as reported by `GET /status`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) enum Stage {
+    Idle,
+    Checking,
+    Staging,
+    PointOfNoReturn,
+    Flashing,
+    Done,
+    Aborted,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Progress {
+    pub stage: Stage,
+    pub percent: u8,
+}
+
+/// Shared state the HTTP handlers read/write; the actual migration runs on
+/// its own thread and just updates this as it goes.
+pub(crate) struct AgentState {
+    pub progress: Progress,
+    pub config: Config,
+    pub abort_requested: bool,
+    pub start_requested: bool,
+}
+
+impl AgentState {
+    pub fn new(config: Config) -> AgentState {
+        AgentState {
+            progress: Progress {
+                stage: Stage::Idle,
+                percent: 0,
+            },
+            config,
+            abort_requested: false,
+            start_requested: false,
+        }
+    }
+}
+
+pub(crate) type SharedState = Arc<Mutex<AgentState>>;
+
+/// Bind `socket_path` and serve the agent API until the process exits. One
+/// thread is spawned per connection - this is a low-traffic control plane,
+/// not something that needs an async runtime.
+pub(crate) fn serve(socket_path: &Path, state: SharedState) -> Result<(), MigError> {
+    if socket_path.exists() {
+        std::fs::remove_file(socket_path).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "{}::serve: failed to remove stale socket '{}'",
+                MODULE,
+                socket_path.display()
+            ),
+        ))?;
+    }
+
+    let listener = UnixListener::bind(socket_path).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!(
+            "{}::serve: failed to bind agent socket '{}'",
+            MODULE,
+            socket_path.display()
+        ),
+    ))?;
+
+    info!("agent API listening on '{}'", socket_path.display());
+
+    for conn in listener.incoming() {
+        match conn {
+            Ok(stream) => {
+                let state = state.clone();
+                thread::spawn(move || {
+                    if let Err(why) = handle_connection(stream, &state) {
+                        error!("{}::serve: error handling connection: {:?}", MODULE, why);
+                    }
+                });
+            }
+            Err(why) => error!("{}::serve: failed to accept connection: {}", MODULE, why),
+        }
+    }
+
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    path: String,
+}
+
+fn handle_connection(mut stream: UnixStream, state: &SharedState) -> Result<(), MigError> {
+    let request = read_request(&mut stream)?;
+    debug!("{}::handle_connection: {} {}", MODULE, request.method, request.path);
+
+    let (status, body) = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => {
+            let state = state.lock().unwrap();
+            (200, serde_json::to_string(&state.progress).unwrap())
+        }
+        ("GET", "/config") => {
+            let state = state.lock().unwrap();
+            let summary = ConfigSummary::from(&state.config);
+            match serde_json::to_string(&summary) {
+                Ok(json) => (200, json),
+                Err(why) => (500, format!("{{\"error\":\"{}\"}}", why)),
+            }
+        }
+        ("PUT", "/start") => {
+            let mut state = state.lock().unwrap();
+            state.start_requested = true;
+            (200, String::from("{\"result\":\"starting\"}"))
+        }
+        ("PUT", "/abort") => {
+            let mut state = state.lock().unwrap();
+            match state.progress.stage {
+                Stage::PointOfNoReturn | Stage::Flashing => (
+                    409,
+                    String::from("{\"error\":\"past the point of no return, cannot abort\"}"),
+                ),
+                _ => {
+                    state.abort_requested = true;
+                    (200, String::from("{\"result\":\"aborting\"}"))
+                }
+            }
+        }
+        _ => (404, String::from("{\"error\":\"not found\"}")),
+    };
+
+    write_response(&mut stream, status, &body)
+}
+
+fn read_request(stream: &mut UnixStream) -> Result<Request, MigError> {
+    let mut raw = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let read = stream.read(&mut buf).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::read_request: failed to read from socket", MODULE),
+        ))?;
+        if read == 0 {
+            break;
+        }
+        raw.extend_from_slice(&buf[..read]);
+        if raw.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let header_str = String::from_utf8_lossy(&raw);
+    let mut lines = header_str.lines();
+    let request_line = lines.next().ok_or_else(|| {
+        MigError::from_remark(MigErrorKind::InvParam, &format!("{}::read_request: empty request", MODULE))
+    })?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::read_request: malformed request line: '{}'", MODULE, request_line),
+            )
+        })?
+        .to_string();
+    let path = parts
+        .next()
+        .ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::read_request: malformed request line: '{}'", MODULE, request_line),
+            )
+        })?
+        .to_string();
+
+    Ok(Request { method, path })
+}
+
+fn write_response(stream: &mut UnixStream, status: u16, body: &str) -> Result<(), MigError> {
+    let status_text = match status {
+        200 => "OK",
+        404 => "Not Found",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        status_text,
+        body.len(),
+        body
+    );
+
+    stream
+        .write_all(response.as_bytes())
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::write_response: failed to write response", MODULE),
+        ))?;
+
+    Ok(())
+}
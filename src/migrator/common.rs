@@ -1,5 +1,3 @@
-//pub mod mig_error;
-use failure::ResultExt;
 use log::trace;
 use std::fmt::{self, Display, Formatter};
 use std::process::{Command, ExitStatus, Stdio};
@@ -17,14 +15,23 @@ pub mod os_release;
 pub use os_release::OSRelease;
 
 pub mod balena_cfg_json;
+pub mod boot_fs;
+pub mod cmd_resolver;
 pub mod config;
 pub mod config_helper;
+pub mod console_cfg;
+pub mod fetch;
+pub mod file_digest;
 pub mod file_info;
+pub mod http_client;
 pub mod logger;
+pub mod magic;
+pub mod qcow;
+pub mod work_gc;
 pub use logger::Logger;
 
 
-pub use self::mig_error::{MigErrCtx, MigError, MigErrorKind};
+pub use self::mig_error::{MigErrCtx, MigError, MigErrorKind, ResultExt};
 pub use self::config::{Config, MigMode};
 pub use self::file_info::{FileInfo, FileType};
 
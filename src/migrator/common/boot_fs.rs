@@ -0,0 +1,190 @@
+// Stage the kernel, initramfs and balena config.json directly onto a FAT
+// boot partition using the pure-Rust `fatfs` crate, instead of relying on
+// `mount`/`mtools`/`dd` being available (and root) on the source OS.
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+use log::info;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::console_cfg::configure_console;
+use super::{format_size_with_unit, MigErrCtx, MigError, MigErrorKind, ResultExt};
+use crate::migrator::common::config::migrate_config::MigrateConfig;
+
+const MODULE: &str = "common::boot_fs";
+
+/// One file that `stage_boot_files` copied (or, in pretend mode, would copy)
+/// onto the boot partition - reported so PRETEND mode can show exactly what
+/// would be written and how large it is.
+#[derive(Debug)]
+pub(crate) struct StagedFile {
+    pub dest_name: String,
+    pub size: u64,
+}
+
+/// Copy `kernel_file`, `initramfs_file` and `config.json` from the host
+/// filesystem into the root directory of the FAT boot partition at
+/// `boot_part`. If `format` is set, the volume is freshly formatted first.
+/// In `pretend` mode nothing is written - the files that would have been
+/// staged (with their sizes) are simply returned.
+pub(crate) fn stage_boot_files(
+    migrate_cfg: &MigrateConfig,
+    cfg_json_path: &Path,
+    boot_part: &Path,
+    format: bool,
+    pretend: bool,
+) -> Result<Vec<StagedFile>, MigError> {
+    let mut staged = Vec::new();
+
+    for (dest_name, src_path) in &[
+        ("balena.zImage", migrate_cfg.kernel_file.as_str()),
+        ("balena.initramfs.cpio.gz", migrate_cfg.initramfs_file.as_str()),
+    ] {
+        if src_path.is_empty() {
+            continue;
+        }
+        let size = Path::new(src_path)
+            .metadata()
+            .context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!("stage_boot_files: failed to stat '{}'", src_path),
+            ))?
+            .len();
+        staged.push(StagedFile {
+            dest_name: dest_name.to_string(),
+            size,
+        });
+    }
+
+    let cfg_size = cfg_json_path
+        .metadata()
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "stage_boot_files: failed to stat '{}'",
+                cfg_json_path.display()
+            ),
+        ))?
+        .len();
+    staged.push(StagedFile {
+        dest_name: String::from("config.json"),
+        size: cfg_size,
+    });
+
+    if pretend {
+        for file in &staged {
+            info!(
+                "pretend mode: would write '{}' ({}) to boot partition '{}'",
+                file.dest_name,
+                format_size_with_unit(file.size),
+                boot_part.display()
+            );
+        }
+        return Ok(staged);
+    }
+
+    let partition_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(boot_part)
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "stage_boot_files: failed to open boot partition '{}'",
+                boot_part.display()
+            ),
+        ))?;
+
+    if format {
+        fatfs::format_volume(&partition_file, FormatVolumeOptions::new()).context(
+            MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!(
+                    "stage_boot_files: failed to format boot partition '{}'",
+                    boot_part.display()
+                ),
+            ),
+        )?;
+    }
+
+    let fs = FileSystem::new(partition_file, FsOptions::new()).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!(
+            "stage_boot_files: failed to open FAT filesystem on '{}'",
+            boot_part.display()
+        ),
+    ))?;
+    let root_dir = fs.root_dir();
+
+    if !migrate_cfg.kernel_file.is_empty() {
+        copy_into_fat(&migrate_cfg.kernel_file, "balena.zImage", &root_dir)?;
+    }
+
+    if !migrate_cfg.initramfs_file.is_empty() {
+        copy_into_fat(
+            &migrate_cfg.initramfs_file,
+            "balena.initramfs.cpio.gz",
+            &root_dir,
+        )?;
+    }
+
+    copy_into_fat(
+        &cfg_json_path.to_string_lossy(),
+        "config.json",
+        &root_dir,
+    )?;
+
+    if let Some(console) = &migrate_cfg.console {
+        configure_console(console, &root_dir)?;
+    }
+
+    info!(
+        "staged {} files onto boot partition '{}'",
+        staged.len(),
+        boot_part.display()
+    );
+
+    Ok(staged)
+}
+
+fn copy_into_fat<IO: fatfs::ReadWriteSeek, TP, OCC>(
+    src_path: &str,
+    dest_name: &str,
+    root_dir: &fatfs::Dir<IO, TP, OCC>,
+) -> Result<(), MigError>
+where
+    TP: fatfs::TimeProvider,
+    OCC: fatfs::OemCpConverter,
+{
+    let mut src_file = File::open(src_path).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("copy_into_fat: failed to open '{}'", src_path),
+    ))?;
+
+    let mut dest_file = root_dir.create_file(dest_name).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("copy_into_fat: failed to create '{}' on boot partition", dest_name),
+    ))?;
+    dest_file.truncate().context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("copy_into_fat: failed to truncate '{}'", dest_name),
+    ))?;
+
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = src_file.read(&mut buf).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("copy_into_fat: failed to read from '{}'", src_path),
+        ))?;
+        if read == 0 {
+            break;
+        }
+        dest_file.write_all(&buf[..read]).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("copy_into_fat: failed to write to '{}'", dest_name),
+        ))?;
+    }
+
+    Ok(())
+}
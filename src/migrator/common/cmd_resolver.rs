@@ -0,0 +1,101 @@
+// Cross-platform PATH-searching command resolver, replacing the
+// `whereis`-shell-out approach: walks `PATH` (honoring executable bits on
+// Unix and `PATHEXT` on Windows), validates each candidate before
+// accepting it, and caches resolved absolute paths so repeated lookups for
+// the same command are free and so the crate fails fast - naming both the
+// tool and the directories searched - if a command can't be found, instead
+// of discovering that deep into a migration run.
+
+use log::debug;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::{MigError, MigErrorKind};
+
+const MODULE: &str = "common::cmd_resolver";
+
+lazy_static::lazy_static! {
+    static ref RESOLVED: Mutex<HashMap<String, PathBuf>> = Mutex::new(HashMap::new());
+}
+
+/// Resolve `cmd` to an absolute path by searching `PATH`. Results are
+/// cached by command name, so subsequent calls for the same command are a
+/// map lookup rather than a repeat filesystem walk.
+pub(crate) fn resolve(cmd: &str) -> Result<PathBuf, MigError> {
+    if let Some(cached) = RESOLVED.lock().unwrap().get(cmd) {
+        return Ok(cached.clone());
+    }
+
+    let search_dirs: Vec<PathBuf> = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default();
+
+    for dir in &search_dirs {
+        for candidate in candidates(dir, cmd) {
+            if is_valid_executable(&candidate) {
+                debug!(
+                    "{}::resolve: resolved '{}' to '{}'",
+                    MODULE,
+                    cmd,
+                    candidate.display()
+                );
+                RESOLVED
+                    .lock()
+                    .unwrap()
+                    .insert(String::from(cmd), candidate.clone());
+                return Ok(candidate);
+            }
+        }
+    }
+
+    Err(MigError::from_remark(
+        MigErrorKind::NotFound,
+        &format!(
+            "{}::resolve: command '{}' not found, searched: [{}]",
+            MODULE,
+            cmd,
+            search_dirs
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    ))
+}
+
+/// The filenames to try for `cmd` inside `dir` - just `cmd` itself on Unix,
+/// but every `PATHEXT` extension (plus the bare name) on Windows, since
+/// `foo` alone won't match `foo.exe` by file existence alone.
+#[cfg(windows)]
+fn candidates(dir: &Path, cmd: &str) -> Vec<PathBuf> {
+    let pathext = env::var("PATHEXT").unwrap_or_else(|_| String::from(".EXE;.CMD;.BAT;.COM"));
+
+    let mut out = vec![dir.join(cmd)];
+    for ext in pathext.split(';') {
+        if !ext.is_empty() {
+            out.push(dir.join(format!("{}{}", cmd, ext)));
+        }
+    }
+    out
+}
+
+#[cfg(not(windows))]
+fn candidates(dir: &Path, cmd: &str) -> Vec<PathBuf> {
+    vec![dir.join(cmd)]
+}
+
+#[cfg(unix)]
+fn is_valid_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_valid_executable(path: &Path) -> bool {
+    path.metadata().map(|metadata| metadata.is_file()).unwrap_or(false)
+}
@@ -1,14 +1,12 @@
-use failure::ResultExt;
 use log::{debug, error, info, Level};
 use mod_logger::{LogDestination, Logger, NO_STREAM};
 use serde::Deserialize;
 use serde_yaml;
-use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
 
 use clap::{App, Arg};
 
-use super::{MigErrCtx, MigError, MigErrorKind};
+use super::{work_gc, MigErrCtx, MigError, MigErrorKind, ResultExt};
 
 pub(crate) mod migrate_config;
 pub(crate) use migrate_config::{MigMode, MigrateConfig, MigrateWifis};
@@ -19,6 +17,8 @@ pub(crate) use balena_config::BalenaConfig;
 pub mod debug_config;
 pub(crate) use debug_config::DebugConfig;
 
+mod merge;
+
 use crate::{
     common::{file_exists, path_append},
     defs::DEFAULT_MIGRATE_CONFIG,
@@ -222,17 +222,177 @@ impl<'a> Config {
     fn from_file<P: AsRef<Path>>(file_name: &P) -> Result<Config, MigError> {
         let file_name = file_name.as_ref();
         info!("Using config file '{}'", file_name.display());
-        Config::from_string(&read_to_string(file_name).context(MigErrCtx::from_remark(
+
+        // resolve `include:` overlays (if any) and deep-merge them before
+        // deserializing, so device-/fleet-specific overlays don't require
+        // duplicating the whole base config
+        let merged = merge::load_merged(file_name)?;
+        let merged_str = serde_yaml::to_string(&merged).context(MigErrCtx::from_remark(
             MigErrorKind::Upstream,
-            &format!("from_file: failed to read {}", file_name.display()),
-        ))?)
+            &format!(
+                "from_file: failed to re-serialize merged config for '{}'",
+                file_name.display()
+            ),
+        ))?;
+
+        Config::from_string(&merged_str)
     }
 
     fn check(&self) -> Result<(), MigError> {
         self.migrate.check()?;
         let mode = self.migrate.get_mig_mode();
-        self.balena.check(mode)?;
+        self.balena.check(mode, self.migrate.get_work_dir())?;
         self.debug.check(mode)?;
+        self.source_layout_check()?;
+        self.smart_preflight()?;
+        self.fs_check()?;
+        self.collect_garbage()?;
+        Ok(())
+    }
+
+    /// Refuse to continue if the running system's root sits on a source
+    /// layout migrate can't safely handle (LVM, ZFS, iSCSI) - better to abort
+    /// here than flash the wrong disk or silently skip data.
+    #[cfg(target_os = "linux")]
+    fn source_layout_check(&self) -> Result<(), MigError> {
+        use crate::migrator::linux::util::check_source_layout;
+        check_source_layout("/")
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn source_layout_check(&self) -> Result<(), MigError> {
+        Ok(())
+    }
+
+    /// For a filesystem-archives image with a `check:` path configured,
+    /// confirm that path actually exists in the root_a archive before
+    /// trusting it - catches a mislabeled or truncated archive up front
+    /// instead of discovering it mid-flash.
+    #[cfg(target_os = "linux")]
+    fn fs_check(&self) -> Result<(), MigError> {
+        use crate::migrator::linux::fs_inspect::ExtFs;
+        use balena_config::ImageType;
+
+        let fs = match self.balena.get_image_path() {
+            ImageType::FileSystems(fs) => fs,
+            _ => return Ok(()),
+        };
+
+        let check_path = match &fs.check {
+            Some(check_path) => check_path,
+            None => return Ok(()),
+        };
+
+        let root_a = self.migrate.get_work_dir().join(&fs.root_a.archive.path);
+        let mut ext_fs = ExtFs::open(&root_a, 0).map_err(|why| {
+            MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!(
+                    "fs_check: failed to open root_a archive '{}': {}",
+                    root_a.display(),
+                    why
+                ),
+            )
+        })?;
+
+        if !ext_fs.file_exists(check_path) {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!(
+                    "fs_check: '{}' not found in root_a archive '{}'",
+                    check_path,
+                    root_a.display()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn fs_check(&self) -> Result<(), MigError> {
+        Ok(())
+    }
+
+    /// Log available space on `work_dir`'s filesystem (and whether `work_dir`
+    /// is itself a mountpoint), obtained via `statvfs` rather than shelling
+    /// out to `df` - purely informational, so a failure to stat is only
+    /// logged at debug level, not propagated.
+    #[cfg(target_os = "linux")]
+    fn log_work_dir_space(&self, work_dir: &Path) {
+        use crate::migrator::linux::fs_stat::{fs_stat, is_mountpoint};
+
+        match fs_stat(work_dir) {
+            Ok(stat) => info!(
+                "work_dir '{}': {} available of {} total",
+                work_dir.display(),
+                stat.available_bytes,
+                stat.total_bytes
+            ),
+            Err(why) => debug!("failed to stat work_dir '{}': {:?}", work_dir.display(), why),
+        }
+
+        match is_mountpoint(work_dir) {
+            Ok(is_mp) => debug!("work_dir '{}' is a mountpoint: {}", work_dir.display(), is_mp),
+            Err(why) => debug!(
+                "failed to determine whether work_dir '{}' is a mountpoint: {:?}",
+                work_dir.display(),
+                why
+            ),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn log_work_dir_space(&self, _work_dir: &Path) {}
+
+    /// Gate the migration on the target device's SMART health, if
+    /// `migrate.smart_check_device` is configured. `migrate.smart_force`
+    /// downgrades a failing verdict to a warning instead of aborting.
+    #[cfg(target_os = "linux")]
+    fn smart_preflight(&self) -> Result<(), MigError> {
+        use crate::migrator::linux::smart;
+
+        if let Some(device) = &self.migrate.smart_check_device {
+            smart::smart_preflight(device, self.migrate.smart_force)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn smart_preflight(&self) -> Result<(), MigError> {
+        Ok(())
+    }
+
+    /// Prune stale downloaded artifacts from `work_dir`, keeping
+    /// `migrate.retain` most recent ones plus anything the active config
+    /// still references. Dry-runs (logs only, removes nothing) in PRETEND
+    /// mode.
+    fn collect_garbage(&self) -> Result<(), MigError> {
+        let work_dir = self.migrate.get_work_dir();
+        if !work_dir.is_dir() {
+            return Ok(());
+        }
+
+        self.log_work_dir_space(work_dir);
+
+        let mut roots = vec![work_dir.join(&self.balena.get_config_path().path)];
+        roots.extend(
+            self.balena
+                .get_image_path()
+                .file_refs()
+                .into_iter()
+                .map(|file_ref| work_dir.join(&file_ref.path)),
+        );
+        if !self.migrate.kernel_file.is_empty() {
+            roots.push(PathBuf::from(&self.migrate.kernel_file));
+        }
+        if !self.migrate.initramfs_file.is_empty() {
+            roots.push(PathBuf::from(&self.migrate.initramfs_file));
+        }
+
+        let pretend = matches!(self.migrate.get_mig_mode(), MigMode::PRETEND);
+        work_gc::collect_garbage(work_dir, &roots, self.migrate.retain, pretend)?;
+
         Ok(())
     }
 }
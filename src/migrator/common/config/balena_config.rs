@@ -0,0 +1,201 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::migrator::common::config::migrate_config::MigMode;
+use crate::migrator::common::file_digest::HashInfo;
+use crate::migrator::{MigError, MigErrorKind};
+
+const MODULE: &str = "common::config::balena_config";
+const DEFAULT_API_PORT: u16 = 443;
+const DEFAULT_CHECK_TIMEOUT: u64 = 20;
+
+/// A file the config refers to, plus an optional digest used to verify its
+/// integrity before it's used (flashed, written to boot, etc). `path` is
+/// either a local path to use as-is, or - if `url` is set - the name the
+/// remote file is downloaded to under `work_dir` before use.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct FileRef {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub hash: Option<HashInfo>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+impl FileRef {
+    /// If a hash was declared for this file, stream it (relative to
+    /// `work_dir`) through the matching digest and compare. Files with no
+    /// declared hash are considered verified trivially.
+    pub fn verify(&self, work_dir: &std::path::Path) -> Result<(), MigError> {
+        let hash = match &self.hash {
+            Some(hash) => hash,
+            None => return Ok(()),
+        };
+
+        let full_path = work_dir.join(&self.path);
+        if hash.verify(&full_path)? {
+            Ok(())
+        } else {
+            Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!(
+                    "{}::FileRef::verify: hash mismatch for '{}'",
+                    MODULE,
+                    full_path.display()
+                ),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ArchivedPartition {
+    pub blocks: u64,
+    pub archive: FileRef,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct FsImageConfig {
+    pub device_slug: String,
+    #[serde(default)]
+    pub check: Option<String>,
+    #[serde(default)]
+    pub max_data: Option<bool>,
+    #[serde(default)]
+    pub mkfs_direct: Option<bool>,
+    pub extended_blocks: u64,
+    pub boot: ArchivedPartition,
+    pub root_a: ArchivedPartition,
+    pub root_b: ArchivedPartition,
+    pub state: ArchivedPartition,
+    pub data: ArchivedPartition,
+}
+
+/// The two ways migrate knows how to put balenaOS onto the target device:
+/// `dd`-style raw/compressed flasher images, or a set of filesystem archives
+/// written partition by partition.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) enum ImageType {
+    #[serde(rename = "dd")]
+    Flasher(FileRef),
+    #[serde(rename = "fs")]
+    FileSystems(FsImageConfig),
+    /// A `dd`-style flasher image fetched from a remote URL rather than
+    /// staged locally ahead of time - `fetch::resolve` downloads a
+    /// `FileRef`'s `url` into `work_dir` under its `path`. The variant is
+    /// not rewritten into a `Flasher`; it is verified at `work_dir`/`path`
+    /// like any other `FileRef`.
+    #[serde(rename = "url")]
+    Remote(FileRef),
+}
+
+impl ImageType {
+    /// Every `FileRef` this image resolves to - a single archive for
+    /// `Flasher`/`Remote`, or all five partition archives for
+    /// `FileSystems`, so callers that verify or protect-from-GC the image
+    /// (`BalenaConfig::check`, `Config::collect_garbage`) don't silently
+    /// miss `root_a`/`root_b`/`state`/`data`.
+    pub fn file_refs(&self) -> Vec<&FileRef> {
+        match self {
+            ImageType::Flasher(file_ref) | ImageType::Remote(file_ref) => vec![file_ref],
+            ImageType::FileSystems(fs) => vec![
+                &fs.boot.archive,
+                &fs.root_a.archive,
+                &fs.root_b.archive,
+                &fs.state.archive,
+                &fs.data.archive,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ApiConfig {
+    pub host: String,
+    #[serde(default = "ApiConfig::default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub check: bool,
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+impl ApiConfig {
+    fn default_port() -> u16 {
+        DEFAULT_API_PORT
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct BalenaConfig {
+    pub image: ImageType,
+    pub config: FileRef,
+    #[serde(default)]
+    pub app_name: Option<String>,
+    #[serde(default)]
+    pub api: Option<ApiConfig>,
+    #[serde(default)]
+    pub check_vpn: bool,
+    #[serde(default = "BalenaConfig::default_check_timeout")]
+    pub check_timeout: u64,
+}
+
+impl BalenaConfig {
+    fn default_check_timeout() -> u64 {
+        DEFAULT_CHECK_TIMEOUT
+    }
+
+    pub fn get_image_path(&self) -> &ImageType {
+        &self.image
+    }
+
+    pub fn get_config_path(&self) -> &FileRef {
+        &self.config
+    }
+
+    pub fn is_check_vpn(&self) -> bool {
+        self.check_vpn
+    }
+
+    pub fn is_check_api(&self) -> bool {
+        self.api.as_ref().map(|api| api.check).unwrap_or(false)
+    }
+
+    pub fn get_check_timeout(&self) -> u64 {
+        self.check_timeout
+    }
+
+    pub fn get_app_name(&self) -> Option<&str> {
+        self.app_name.as_deref()
+    }
+
+    pub fn set_image_path(&mut self, path: &str) {
+        self.image = ImageType::Flasher(FileRef {
+            path: PathBuf::from(path),
+            hash: None,
+            url: None,
+            signature: None,
+        });
+    }
+
+    pub fn check(&self, mode: &MigMode, work_dir: &std::path::Path) -> Result<(), MigError> {
+        match mode {
+            MigMode::INVALID => {
+                return Err(MigError::from_remark(
+                    MigErrorKind::InvState,
+                    &format!("{}::check: invalid migrate mode", MODULE),
+                ));
+            }
+            _ => (),
+        }
+
+        self.config.verify(work_dir)?;
+        for file_ref in self.image.file_refs() {
+            file_ref.verify(work_dir)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+// Deep-merge support for `include:` config overlays: a base config can list
+// device- or fleet-specific overlay files, which get merged on top of it
+// key-by-key before the result is deserialized into `Config`. Mirrors the
+// "pass an entire config fragment" composition model used by tools like
+// Liminix/nixpkgs modules, just over `serde_yaml::Value` instead of Nix
+// expressions.
+
+use serde_yaml::Value;
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use super::super::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "common::config::merge";
+const INCLUDE_KEY: &str = "include";
+const APPEND_MARKER: &str = "+append";
+
+/// Load `path` as YAML, recursively resolving and merging any `include:`
+/// overlays (relative to the including file's directory), and return the
+/// fully merged `Value` tree ready for `Config` deserialization.
+pub(crate) fn load_merged<P: AsRef<Path>>(path: P) -> Result<Value, MigError> {
+    let mut visited = HashSet::new();
+    load_merged_inner(path.as_ref(), &mut visited)
+}
+
+fn load_merged_inner(path: &Path, visited: &mut HashSet<PathBuf>) -> Result<Value, MigError> {
+    let canonical = path.canonicalize().context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::load_merged: failed to canonicalize '{}'", MODULE, path.display()),
+    ))?;
+
+    if !visited.insert(canonical.clone()) {
+        return Err(MigError::from_remark(
+            MigErrorKind::InvParam,
+            &format!(
+                "{}::load_merged: include cycle detected at '{}'",
+                MODULE,
+                canonical.display()
+            ),
+        ));
+    }
+
+    let content = read_to_string(path).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::load_merged: failed to read '{}'", MODULE, path.display()),
+    ))?;
+
+    // auto-detect TOML vs YAML from the file extension, so the top-level
+    // config and its `include:` overlays can each be in either format
+    let mut value: Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).context(MigErrCtx::from_remark(
+            MigErrorKind::InvParam,
+            &format!("{}::load_merged: failed to parse TOML from '{}'", MODULE, path.display()),
+        ))?,
+        _ => serde_yaml::from_str(&content).context(MigErrCtx::from_remark(
+            MigErrorKind::InvParam,
+            &format!("{}::load_merged: failed to parse '{}'", MODULE, path.display()),
+        ))?,
+    };
+
+    let includes = take_includes(&mut value)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    // the including file is the base; its overlays go on top of it in the
+    // order they are listed, so a later include wins over an earlier one
+    let mut merged = value;
+    for include in includes {
+        let include_path = base_dir.join(&include);
+        let overlay = load_merged_inner(&include_path, visited)?;
+        merged = deep_merge(merged, overlay);
+    }
+
+    visited.remove(&canonical);
+
+    Ok(merged)
+}
+
+/// Pop the `include:` key (a list of file paths) off a just-parsed document,
+/// so it isn't mistaken for a real config key once merged.
+fn take_includes(value: &mut Value) -> Result<Vec<String>, MigError> {
+    let map = match value.as_mapping_mut() {
+        Some(map) => map,
+        None => return Ok(Vec::new()),
+    };
+
+    let include_key = Value::String(String::from(INCLUDE_KEY));
+    let includes = match map.remove(&include_key) {
+        Some(Value::Sequence(seq)) => seq
+            .into_iter()
+            .map(|v| {
+                v.as_str().map(String::from).ok_or_else(|| {
+                    MigError::from_remark(
+                        MigErrorKind::InvParam,
+                        &format!("{}::take_includes: 'include' entries must be strings", MODULE),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(_) => {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::take_includes: 'include' must be a list of paths", MODULE),
+            ));
+        }
+        None => Vec::new(),
+    };
+
+    Ok(includes)
+}
+
+/// Merge `overlay` on top of `base`: scalars and maps are overridden/extended
+/// key by key; sequences are replaced by default. An overlay key named
+/// `"<key>+append"` instead of `"<key>"` appends its sequence to the base
+/// sequence at `<key>` rather than replacing it.
+fn deep_merge(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Mapping(mut base_map), Value::Mapping(overlay_map)) => {
+            for (overlay_key, overlay_val) in overlay_map {
+                let (real_key, append) = split_append_marker(&overlay_key);
+
+                let merged_val = match base_map.remove(&real_key) {
+                    Some(base_val) if append => append_sequence(base_val, overlay_val),
+                    Some(base_val) => deep_merge(base_val, overlay_val),
+                    None => overlay_val,
+                };
+                base_map.insert(real_key, merged_val);
+            }
+            Value::Mapping(base_map)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+/// Split `"foo+append"` into (`"foo"`, true); any other key comes back
+/// unchanged with `append = false`.
+fn split_append_marker(key: &Value) -> (Value, bool) {
+    match key.as_str() {
+        Some(s) if s.ends_with(APPEND_MARKER) => (
+            Value::String(String::from(&s[..s.len() - APPEND_MARKER.len()])),
+            true,
+        ),
+        _ => (key.clone(), false),
+    }
+}
+
+fn append_sequence(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Sequence(mut base_seq), Value::Sequence(overlay_seq)) => {
+            base_seq.extend(overlay_seq);
+            Value::Sequence(base_seq)
+        }
+        (_, overlay) => overlay,
+    }
+}
@@ -1,10 +1,9 @@
-use super::{get_yaml_bool, get_yaml_int, get_yaml_str, get_yaml_val, LogConfig, YamlConfig};
-use crate::migrator::{MigError, MigErrorKind};
-use yaml_rust::Yaml;
+use super::super::console_cfg::ConsoleConfig;
+use super::LogConfig;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::Serialize;
 
-const MODULE: &str = "common::config::migrate_config";
-
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum MigMode {
     INVALID,
     AGENT,
@@ -14,124 +13,83 @@ pub enum MigMode {
 
 const DEFAULT_MODE: MigMode = MigMode::INVALID;
 
-#[derive(Debug)]
+impl<'de> Deserialize<'de> for MigMode {
+    // accepts the same `immediate|agent|pretend` strings the YAML loader has
+    // always taken, whichever format (YAML or TOML) they arrive from
+    fn deserialize<D>(deserializer: D) -> Result<MigMode, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mode = String::deserialize(deserializer)?;
+        match mode.to_lowercase().as_str() {
+            "immediate" => Ok(MigMode::IMMEDIATE),
+            "agent" => Ok(MigMode::AGENT),
+            "pretend" => Ok(MigMode::PRETEND),
+            _ => Err(de::Error::custom(format!(
+                "invalid value for migrate mode '{}'",
+                mode
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
 pub struct MigrateConfig {
+    #[serde(default = "MigrateConfig::default_work_dir")]
     pub work_dir: String,
+    #[serde(default = "MigrateConfig::default_mode")]
     pub mode: MigMode,
+    #[serde(default)]
     pub reboot: Option<u64>,
+    #[serde(default)]
     pub all_wifis: bool,
+    #[serde(default)]
     pub log_to: Option<LogConfig>,
+    #[serde(default)]
     pub kernel_file: String,
+    #[serde(default)]
     pub initramfs_file: String,
+    #[serde(default)]
     pub force_slug: Option<String>,
+    #[serde(default)]
+    pub console: Option<ConsoleConfig>,
+    /// Maximum number of downloaded work_dir artifacts (besides files the
+    /// active config still references) to keep around; `None` is unlimited.
+    #[serde(default)]
+    pub retain: Option<usize>,
+    /// Device to run the SMART pre-flight health check against before
+    /// flashing (typically the target/boot device); `None` skips the check.
+    #[serde(default)]
+    pub smart_check_device: Option<String>,
+    /// Continue (with only a warning) past a failing/near-end-of-life SMART
+    /// verdict instead of aborting the migration.
+    #[serde(default)]
+    pub smart_force: bool,
 }
 
 impl MigrateConfig {
     pub fn default() -> MigrateConfig {
         MigrateConfig {
-            work_dir: String::from("."),
-            mode: DEFAULT_MODE,
+            work_dir: Self::default_work_dir(),
+            mode: Self::default_mode(),
             reboot: None,
             all_wifis: false,
             log_to: None,
             kernel_file: String::from(""),
             initramfs_file: String::from(""),
             force_slug: None,
+            console: None,
+            retain: None,
+            smart_check_device: None,
+            smart_force: false,
         }
     }
-}
-
-impl YamlConfig for MigrateConfig {
-    fn to_yaml(&self, prefix: &str) -> String {
-        let mut output = format!(
-            "{}migrate:\n{}  work_dir: '{}'\n{}  mode: '{:?}'\n{}  all_wifis: {}\n",
-            prefix, prefix, self.work_dir, prefix, self.mode, prefix, self.all_wifis
-        );
-        if let Some(i) = self.reboot {
-            output += &format!("{}  reboot: {}\n", prefix, i);
-        }
-
-        if self.kernel_file.is_empty() == false {
-            output += &format!("{}  kernel_file: {}\n", prefix, self.kernel_file);
-        }
-
-        if self.initramfs_file.is_empty() == false {
-            output += &format!("{}  initramfs_file: {}\n", prefix, self.initramfs_file);
-        }
-
-        if let Some(slug) = &self.force_slug {
-            output += &format!("{}  force_slug: '{}'\n", prefix, slug);
-        }
-
-        let next_prefix = String::from(prefix) + "  ";
-        if let Some(ref log_to) = self.log_to {
-            output += &log_to.to_yaml(&next_prefix);
-        }
 
-        output
+    fn default_work_dir() -> String {
+        String::from(".")
     }
 
-    fn from_yaml(&mut self, yaml: &Yaml) -> Result<(), MigError> {
-        if let Some(work_dir) = get_yaml_str(yaml, &["work_dir"])? {
-            self.work_dir = String::from(work_dir);
-        }
-
-        if let Some(kernel_file) = get_yaml_str(yaml, &["kernel_file"])? {
-            self.kernel_file = String::from(kernel_file);
-        }
-
-        if let Some(initramfs_file) = get_yaml_str(yaml, &["initramfs_file"])? {
-            self.initramfs_file = String::from(initramfs_file);
-        }
-
-        if let Some(mode) = get_yaml_str(yaml, &["mode"])? {
-            if mode.to_lowercase() == "immediate" {
-                self.mode = MigMode::IMMEDIATE;
-            } else if mode.to_lowercase() == "agent" {
-                self.mode = MigMode::AGENT;
-            } else if mode.to_lowercase() == "pretend" {
-                self.mode = MigMode::PRETEND;
-            } else {
-                return Err(MigError::from_remark(
-                    MigErrorKind::InvParam,
-                    &format!(
-                        "{}::from_string: invalid value for migrate mode '{}'",
-                        MODULE, mode
-                    ),
-                ));
-            }
-        }
-
-        // Param: reboot - must be > 0
-        if let Some(reboot_timeout) = get_yaml_int(yaml, &["reboot"])? {
-            if reboot_timeout > 0 {
-                self.reboot = Some(reboot_timeout as u64);
-            } else {
-                self.reboot = None;
-            }
-        }
-
-        // Param: all_wifis - must be > 0
-        if let Some(all_wifis) = get_yaml_bool(yaml, &["all_wifis"])? {
-            self.all_wifis = all_wifis;
-        }
-
-        // Params: log_to: drive, fs_type
-        if let Some(log_section) = get_yaml_val(yaml, &["log_to"])? {
-            if let Some(ref mut log_to) = self.log_to {
-                log_to.from_yaml(yaml)?;
-            } else {
-                let mut log_to = LogConfig::default();
-                log_to.from_yaml(log_section)?;
-                self.log_to = Some(log_to);
-            }
-        }
-
-        // Param: all_wifis - must be > 0
-        if let Some(force_slug) = get_yaml_str(yaml, &["force_slug"])? {
-            self.force_slug = Some(String::from(force_slug));
-        }
-
-        Ok(())
+    fn default_mode() -> MigMode {
+        DEFAULT_MODE
     }
 }
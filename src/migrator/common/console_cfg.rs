@@ -0,0 +1,199 @@
+// Auto-configure the serial/VGA console of the flashed balenaOS bootloader,
+// the way coreos-installer rewrites the console settings block in
+// `grub.cfg`: locate a delimited `# MIGRATE-CONSOLE-START`/`END` region
+// (inserting one at the end if absent) and regenerate the kernel console
+// args and grub terminal directives between the markers. Re-running is
+// idempotent since the whole block is replaced rather than appended to.
+
+use log::info;
+use regex::Regex;
+use serde::Deserialize;
+use std::io::{Read, Write};
+
+use super::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "common::console_cfg";
+const GRUB_CFG_FILE: &str = "EFI/BOOT/grub.cfg";
+const MARKER_START: &str = "# MIGRATE-CONSOLE-START";
+const MARKER_END: &str = "# MIGRATE-CONSOLE-END";
+
+fn default_baud() -> u32 {
+    115200
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ConsoleConfig {
+    pub device: String,
+    #[serde(default = "default_baud")]
+    pub baud: u32,
+    #[serde(default)]
+    pub keep_vga: bool,
+}
+
+impl ConsoleConfig {
+    /// The kernel command line fragment selecting this console - VGA first
+    /// when kept, so the serial console stays last (and thus active).
+    pub fn kernel_args(&self) -> String {
+        let mut args = Vec::new();
+        if self.keep_vga {
+            args.push(String::from("console=tty0"));
+        }
+        args.push(format!("console={},{}", self.device, self.baud));
+        args.join(" ")
+    }
+
+    fn grub_directives(&self) -> String {
+        let terminal = if self.keep_vga {
+            "terminal_input console serial\nterminal_output console serial"
+        } else {
+            "terminal_input serial\nterminal_output serial"
+        };
+        format!("serial --unit=0 --speed={}\n{}", self.baud, terminal)
+    }
+}
+
+/// Regenerate the `# MIGRATE-CONSOLE-START`/`END` block in `grub_cfg` for
+/// `console`, replacing an existing block or appending a new one.
+pub(crate) fn rewrite_grub_cfg(grub_cfg: &str, console: &ConsoleConfig) -> String {
+    let block = format!(
+        "{start}\nset migrate_console_args=\"{args}\"\n{directives}\n{end}",
+        start = MARKER_START,
+        args = console.kernel_args(),
+        directives = console.grub_directives(),
+        end = MARKER_END,
+    );
+
+    let region = Regex::new(&format!(
+        r"(?s){}.*?{}",
+        regex::escape(MARKER_START),
+        regex::escape(MARKER_END)
+    ))
+    .expect("MARKER_START/MARKER_END form a valid regex");
+
+    if region.is_match(grub_cfg) {
+        region.replace(grub_cfg, block.as_str()).into_owned()
+    } else {
+        let mut rewritten = String::from(grub_cfg);
+        if !rewritten.is_empty() && !rewritten.ends_with('\n') {
+            rewritten.push('\n');
+        }
+        rewritten.push_str(&block);
+        rewritten.push('\n');
+        rewritten
+    }
+}
+
+/// Apply `console` to the balenaOS bootloader config already staged on
+/// `root_dir` (the boot partition's FAT root directory).
+pub(crate) fn configure_console<IO, TP, OCC>(
+    console: &ConsoleConfig,
+    root_dir: &fatfs::Dir<IO, TP, OCC>,
+) -> Result<(), MigError>
+where
+    IO: fatfs::ReadWriteSeek,
+    TP: fatfs::TimeProvider,
+    OCC: fatfs::OemCpConverter,
+{
+    let mut content = String::new();
+    root_dir
+        .open_file(GRUB_CFG_FILE)
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::configure_console: failed to open '{}'", MODULE, GRUB_CFG_FILE),
+        ))?
+        .read_to_string(&mut content)
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::configure_console: failed to read '{}'", MODULE, GRUB_CFG_FILE),
+        ))?;
+
+    let updated = rewrite_grub_cfg(&content, console);
+
+    let mut dest_file = root_dir
+        .create_file(GRUB_CFG_FILE)
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::configure_console: failed to reopen '{}'", MODULE, GRUB_CFG_FILE),
+        ))?;
+    dest_file.truncate().context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::configure_console: failed to truncate '{}'", MODULE, GRUB_CFG_FILE),
+    ))?;
+    dest_file
+        .write_all(updated.as_bytes())
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::configure_console: failed to write '{}'", MODULE, GRUB_CFG_FILE),
+        ))?;
+
+    info!(
+        "applied console config (device '{}', {} baud) to '{}'",
+        console.device, console.baud, GRUB_CFG_FILE
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn console(keep_vga: bool) -> ConsoleConfig {
+        ConsoleConfig {
+            device: String::from("ttyS0"),
+            baud: 115200,
+            keep_vga,
+        }
+    }
+
+    #[test]
+    fn kernel_args_serial_only() {
+        assert_eq!(console(false).kernel_args(), "console=ttyS0,115200");
+    }
+
+    #[test]
+    fn kernel_args_keep_vga_puts_serial_last() {
+        assert_eq!(
+            console(true).kernel_args(),
+            "console=tty0 console=ttyS0,115200"
+        );
+    }
+
+    #[test]
+    fn rewrite_appends_block_when_absent() {
+        let grub_cfg = "set timeout=5\nmenuentry 'balenaOS' {\n}\n";
+        let rewritten = rewrite_grub_cfg(grub_cfg, &console(false));
+
+        assert!(rewritten.starts_with(grub_cfg));
+        assert!(rewritten.contains(MARKER_START));
+        assert!(rewritten.contains(MARKER_END));
+        assert!(rewritten.contains("console=ttyS0,115200"));
+        assert!(rewritten.contains("terminal_input serial"));
+    }
+
+    #[test]
+    fn rewrite_replaces_existing_block_in_place() {
+        let grub_cfg = format!(
+            "set timeout=5\n{}\nset migrate_console_args=\"console=ttyUSB0,9600\"\n{}\nmenuentry 'balenaOS' {{\n}}\n",
+            MARKER_START, MARKER_END
+        );
+
+        let rewritten = rewrite_grub_cfg(&grub_cfg, &console(false));
+
+        assert!(!rewritten.contains("ttyUSB0"));
+        assert!(rewritten.contains("console=ttyS0,115200"));
+        assert!(rewritten.contains("menuentry 'balenaOS'"));
+        // exactly one block survives the rewrite
+        assert_eq!(rewritten.matches(MARKER_START).count(), 1);
+        assert_eq!(rewritten.matches(MARKER_END).count(), 1);
+    }
+
+    #[test]
+    fn rewrite_is_idempotent() {
+        let grub_cfg = "set timeout=5\n";
+        let once = rewrite_grub_cfg(grub_cfg, &console(true));
+        let twice = rewrite_grub_cfg(&once, &console(true));
+
+        assert_eq!(once, twice);
+    }
+}
@@ -0,0 +1,129 @@
+// Download a remote OS image/config referenced via `url:` into `work_dir`
+// and verify it before it's trusted for flashing: the declared `hash:` is
+// checked via `file_digest::HashInfo`, and an optional detached GPG/PGP
+// signature (`signature:`) via the `gpg` binary.
+
+use log::info;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use super::config::balena_config::FileRef;
+use super::http_client::download_to_file;
+use super::{call, MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "common::fetch";
+const GPG_CMD: &str = "gpg";
+
+/// If `file_ref` has a `url`, download it into `work_dir` (named after
+/// `file_ref.path`) and verify its hash/signature; otherwise treat
+/// `file_ref.path` as already staged locally. Returns the path to the file
+/// to actually use.
+pub(crate) fn resolve(file_ref: &FileRef, work_dir: &Path, timeout: u64) -> Result<PathBuf, MigError> {
+    let dest_path = work_dir.join(&file_ref.path);
+
+    if let Some(url) = &file_ref.url {
+        fetch_and_verify(url, file_ref, &dest_path, timeout)?;
+    } else if let Some(hash) = &file_ref.hash {
+        if !hash.verify(&dest_path)? {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!(
+                    "resolve: hash mismatch for locally staged file '{}'",
+                    dest_path.display()
+                ),
+            ));
+        }
+    }
+
+    Ok(dest_path)
+}
+
+fn fetch_and_verify(
+    url: &str,
+    file_ref: &FileRef,
+    dest_path: &Path,
+    timeout: u64,
+) -> Result<(), MigError> {
+    info!("fetching '{}' -> '{}'", url, dest_path.display());
+
+    let path = url_path(url)?;
+    let mut dest_file = File::create(dest_path).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("fetch_and_verify: failed to create '{}'", dest_path.display()),
+    ))?;
+
+    download_to_file(url, &path, &mut dest_file, timeout)?;
+
+    if let Some(hash) = &file_ref.hash {
+        if !hash.verify(dest_path)? {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!(
+                    "fetch_and_verify: downloaded file '{}' does not match declared hash",
+                    dest_path.display()
+                ),
+            ));
+        }
+        info!("'{}' matches declared hash", dest_path.display());
+    }
+
+    if let Some(signature_url) = &file_ref.signature {
+        verify_signature(dest_path, signature_url, work_dir_of(dest_path), timeout)?;
+    }
+
+    Ok(())
+}
+
+fn work_dir_of(dest_path: &Path) -> &Path {
+    dest_path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+fn verify_signature(
+    file_path: &Path,
+    signature_src: &str,
+    work_dir: &Path,
+    timeout: u64,
+) -> Result<(), MigError> {
+    let sig_path = if signature_src.starts_with("http://") || signature_src.starts_with("https://") {
+        let sig_path = work_dir.join(format!(
+            "{}.sig",
+            file_path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let mut sig_file = File::create(&sig_path).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("verify_signature: failed to create '{}'", sig_path.display()),
+        ))?;
+        download_to_file(signature_src, &url_path(signature_src)?, &mut sig_file, timeout)?;
+        sig_path
+    } else {
+        PathBuf::from(signature_src)
+    };
+
+    let path_str = file_path.to_string_lossy();
+    let sig_str = sig_path.to_string_lossy();
+    let args: Vec<&str> = vec!["--verify", &sig_str, &path_str];
+
+    let cmd_res = call(GPG_CMD, &args, true)?;
+    if !cmd_res.status.success() {
+        return Err(MigError::from_remark(
+            MigErrorKind::InvParam,
+            &format!(
+                "{}::verify_signature: signature verification failed for '{}': {}",
+                MODULE,
+                file_path.display(),
+                cmd_res.stderr
+            ),
+        ));
+    }
+
+    info!("detached signature for '{}' verified ok", file_path.display());
+    Ok(())
+}
+
+fn url_path(url: &str) -> Result<String, MigError> {
+    let parsed = url::Url::parse(url).context(MigErrCtx::from_remark(
+        MigErrorKind::InvParam,
+        &format!("url_path: invalid url '{}'", url),
+    ))?;
+    Ok(String::from(parsed.path()))
+}
@@ -0,0 +1,101 @@
+// Digest support for `FileRef` integrity checks. Kept separate from
+// `balena_config` so the set of supported algorithms can grow (we only had
+// MD5 for a long time) without touching the config structs themselves.
+
+use digest::Digest;
+use md5::Md5;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::Sha256;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::migrator::{MigError, MigErrorKind};
+
+const MODULE: &str = "common::file_digest";
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) enum HashInfo {
+    #[serde(rename = "md5")]
+    Md5(String),
+    #[serde(rename = "sha1")]
+    Sha1(String),
+    #[serde(rename = "sha256")]
+    Sha256(String),
+}
+
+impl HashInfo {
+    fn expected(&self) -> &str {
+        match self {
+            HashInfo::Md5(h) | HashInfo::Sha1(h) | HashInfo::Sha256(h) => h,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            HashInfo::Md5(_) => "md5",
+            HashInfo::Sha1(_) => "sha1",
+            HashInfo::Sha256(_) => "sha256",
+        }
+    }
+
+    /// Stream `path` through the algorithm this `HashInfo` names, in fixed
+    /// size chunks rather than reading a (possibly multi-gigabyte) image
+    /// into memory whole, and compare the result against the declared digest.
+    pub fn verify<P: AsRef<Path>>(&self, path: P) -> Result<bool, MigError> {
+        let path = path.as_ref();
+        let digest = match self {
+            HashInfo::Md5(_) => digest_file::<Md5, P>(path)?,
+            HashInfo::Sha1(_) => digest_file::<Sha1, P>(path)?,
+            HashInfo::Sha256(_) => digest_file::<Sha256, P>(path)?,
+        };
+
+        let matches = digest.eq_ignore_ascii_case(self.expected());
+        log::debug!(
+            "{}::verify: {} '{}': expected {}, got {}, match: {}",
+            MODULE,
+            self.name(),
+            path.display(),
+            self.expected(),
+            digest,
+            matches
+        );
+        Ok(matches)
+    }
+}
+
+/// The SHA-256 digest of `path`, hex-encoded - used to dedupe artifacts by
+/// content rather than by name (e.g. work_dir garbage collection).
+pub(crate) fn sha256_hex<P: AsRef<Path>>(path: P) -> Result<String, MigError> {
+    digest_file::<Sha256, P>(path)
+}
+
+fn digest_file<D: Digest, P: AsRef<Path>>(path: P) -> Result<String, MigError> {
+    let path = path.as_ref();
+    let mut file = File::open(path).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::digest_file: failed to open '{}': {}", MODULE, path.display(), why),
+        )
+    })?;
+
+    let mut hasher = D::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    loop {
+        let read = file.read(&mut buf).map_err(|why| {
+            MigError::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::digest_file: failed to read '{}': {}", MODULE, path.display(), why),
+            )
+        })?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
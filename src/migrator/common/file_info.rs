@@ -1,8 +1,7 @@
-use failure::ResultExt;
-#[cfg(target_os = "linux")]
-use lazy_static::lazy_static;
-use log::{debug, error, trace};
-use regex::Regex;
+use flate2::read::GzDecoder;
+use log::{error, trace};
+use std::fs::File;
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
 
 // ******************************************************************
@@ -10,22 +9,50 @@ use std::path::{Path, PathBuf};
 // make a guess on file contents / type and conpare to expected value
 // ******************************************************************
 
-// file on ubuntu-14.04 reports x86 boot sector for image and kernel files
+mod error;
 
-const OS_IMG_FTYPE_REGEX: &str =
-    r#"^(DOS/MBR boot sector|x86 boot sector).*\(gzip compressed data.*\)$"#;
-const INITRD_FTYPE_REGEX: &str = r#"^ASCII cpio archive.*\(gzip compressed data.*\)$"#;
-const OS_CFG_FTYPE_REGEX: &str = r#"^ASCII text.*$"#;
-const KERNEL_AMD64_FTYPE_REGEX: &str =
-    r#"^(Linux kernel x86 boot executable bzImage|x86 boot sector).*$"#;
-const KERNEL_ARMHF_FTYPE_REGEX: &str = r#"^Linux kernel ARM boot executable zImage.*$"#;
-const KERNEL_I386_FTYPE_REGEX: &str = r#"^Linux kernel i386 boot executable bzImage.*$"#;
-const TEXT_FTYPE_REGEX: &str = r#"^ASCII text.*$"#;
-const DTB_FTYPE_REGEX: &str = r#"^(Device Tree Blob|data).*$"#;
+pub(crate) use super::magic::CompressionType;
+use super::file_digest::sha256_hex;
+use super::magic;
+use crate::common::{call, file_exists, MigError, MigErrorKind};
+use error::ctx;
 
-use crate::common::{file_exists, MigErrCtx, MigError, MigErrorKind};
-#[cfg(target_os = "linux")]
-use crate::linux::{EnsuredCmds, FILE_CMD};
+// a stream needing more than this to decompress is almost certainly not
+// going to fit a typical embedded/industrial target's RAM either
+const DEFAULT_XZ_MEM_LIMIT_MB: u64 = 512;
+const GPG_CMD: &str = "gpg";
+
+/// How thoroughly an image/kernel is checked before it's allowed to be
+/// flashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VerifyMode {
+    /// No integrity/authenticity checks at all.
+    Off,
+    /// Require a matching SHA-256 digest.
+    ChecksumOnly,
+    /// Require a matching SHA-256 digest AND a valid detached signature.
+    ChecksumAndSignature,
+}
+
+/// Proof that a `FileInfo` passed `VerifyMode`'s checks - only constructed
+/// by `FileInfo::verify`, so callers are statically encouraged to flash a
+/// `Verified` image rather than a bare, unchecked `FileInfo`.
+#[derive(Debug)]
+pub(crate) struct Verified {
+    info: FileInfo,
+}
+
+impl Verified {
+    pub fn path(&self) -> &Path {
+        &self.info.path
+    }
+
+    /// `FileInfo::expect_type`, but only reachable once `self` has already
+    /// passed its configured integrity/authenticity checks.
+    pub fn expect_type(&self, ftype: &FileType) -> Result<(), MigError> {
+        self.info.expect_type(ftype)
+    }
+}
 
 #[derive(Debug)]
 pub(crate) enum FileType {
@@ -54,7 +81,7 @@ impl FileType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct FileInfo {
     pub path: PathBuf,
     pub size: u64,
@@ -95,10 +122,11 @@ impl FileInfo {
         };
 
         let abs_path = checked_path.canonicalize().unwrap();
-        let metadata = abs_path.metadata().context(MigErrCtx::from_remark(
+        let metadata = ctx!(
+            abs_path.metadata(),
             MigErrorKind::Upstream,
-            &format!("failed to retrieve metadata for path {:?}", abs_path),
-        ))?;
+            format!("failed to retrieve metadata for path {:?}", abs_path)
+        )?;
 
         Ok(Some(FileInfo {
             path: abs_path,
@@ -106,9 +134,8 @@ impl FileInfo {
         }))
     }
 
-    #[cfg(target_os = "linux")]
-    pub fn expect_type(&self, cmds: &EnsuredCmds, ftype: &FileType) -> Result<(), MigError> {
-        if !self.is_type(cmds, ftype)? {
+    pub fn expect_type(&self, ftype: &FileType) -> Result<(), MigError> {
+        if !self.is_type(ftype)? {
             let message = format!(
                 "Could not determine expected file type '{}' for file '{}'",
                 ftype.get_descr(),
@@ -121,68 +148,162 @@ impl FileInfo {
         }
     }
 
-    #[cfg(target_os = "linux")]
-    pub fn is_type(&self, cmds: &EnsuredCmds, ftype: &FileType) -> Result<bool, MigError> {
-        let path_str = self.path.to_string_lossy();
-        let args: Vec<&str> = vec!["-bz", &path_str];
+    /// Inspect `self`'s magic bytes directly rather than shelling out to
+    /// the `file` command and regex-matching its (locale-dependent) prose
+    /// output - this makes detection identical on Linux and Windows.
+    pub fn is_type(&self, ftype: &FileType) -> Result<bool, MigError> {
+        magic::is_type(&self.path, ftype)
+    }
+
+    /// The compression codec (if any) wrapping `self`, sniffed from its
+    /// magic bytes.
+    pub fn compression(&self) -> Result<CompressionType, MigError> {
+        magic::detect_compression(&self.path)
+    }
+
+    /// Open `self` and return a streaming decoder matching its detected
+    /// compression codec, so downstream flashing code always consumes a
+    /// uniform uncompressed byte stream regardless of whether the image
+    /// shipped as gzip, xz or zstd.
+    ///
+    /// For xz, `xz_mem_limit_mb` bounds the decompression memory the
+    /// dictionary window is allowed to use (falling back to
+    /// `DEFAULT_XZ_MEM_LIMIT_MB` if not given); a stream that needs more
+    /// fails fast here with a clear error, rather than opaquely mid-flash.
+    pub fn open_decompressed(&self, xz_mem_limit_mb: Option<u64>) -> Result<Box<dyn Read>, MigError> {
+        let file = ctx!(
+            File::open(&self.path),
+            MigErrorKind::Upstream,
+            format!("open_decompressed: failed to open '{}'", self.path.display())
+        )?;
+
+        match self.compression()? {
+            CompressionType::None => Ok(Box::new(file)),
+            CompressionType::Gzip => Ok(Box::new(GzDecoder::new(file))),
+            CompressionType::Zstd => Ok(Box::new(ctx!(
+                zstd::stream::read::Decoder::new(file),
+                MigErrorKind::Upstream,
+                format!(
+                    "open_decompressed: failed to initialize zstd decoder for '{}'",
+                    self.path.display()
+                )
+            )?)),
+            CompressionType::Xz => {
+                let mem_limit_mb = xz_mem_limit_mb.unwrap_or(DEFAULT_XZ_MEM_LIMIT_MB);
+                let mem_limit = mem_limit_mb.saturating_mul(1024 * 1024);
+
+                let stream = ctx!(
+                    xz2::stream::Stream::new_stream_decoder(mem_limit, 0),
+                    MigErrorKind::Upstream,
+                    format!(
+                        "open_decompressed: failed to initialize xz decoder for '{}'",
+                        self.path.display()
+                    )
+                )?;
+                let mut decoder = xz2::read::XzDecoder::new_stream(file, stream);
+
+                // Probe a small read up front so a dictionary window that
+                // exceeds mem_limit is reported clearly before flashing
+                // starts, rather than failing partway through the image.
+                let mut probe = [0u8; 8192];
+                let probed = decoder.read(&mut probe).map_err(|why| {
+                    MigError::from_remark(
+                        MigErrorKind::InvParam,
+                        &format!(
+                            "open_decompressed: xz stream '{}' needs more than the configured {} MiB decompression memory limit: {}",
+                            self.path.display(),
+                            mem_limit_mb,
+                            why
+                        ),
+                    )
+                })?;
+
+                Ok(Box::new(Cursor::new(probe[..probed].to_vec()).chain(decoder)))
+            }
+        }
+    }
 
-        let cmd_res = cmds.call(FILE_CMD, &args, true)?;
-        if !cmd_res.status.success() || cmd_res.stdout.is_empty() {
+    /// Stream `self` (without decompressing it) through SHA-256 and
+    /// compare against the hex-encoded `expected` digest.
+    pub fn verify_sha256(&self, expected: &str) -> Result<bool, MigError> {
+        let digest = sha256_hex(&self.path)?;
+        Ok(digest.eq_ignore_ascii_case(expected))
+    }
+
+    /// Verify a detached signature over `self`, having first imported
+    /// `public_key_path` so the check doesn't depend on whatever happens
+    /// to already be in the operator's `gpg` keyring.
+    pub fn verify_signature(
+        &self,
+        public_key_path: &Path,
+        signature_path: &Path,
+    ) -> Result<(), MigError> {
+        let key_str = public_key_path.to_string_lossy();
+        let import_res = call(GPG_CMD, &["--import", &key_str], true)?;
+        if !import_res.status.success() {
             return Err(MigError::from_remark(
-                MigErrorKind::InvParam,
+                MigErrorKind::Upstream,
                 &format!(
-                    "new: failed determine type for file {}",
-                    self.path.display()
+                    "verify_signature: failed to import public key '{}': {}",
+                    public_key_path.display(),
+                    import_res.stderr
                 ),
             ));
         }
 
-        lazy_static! {
-            static ref OS_IMG_FTYPE_RE: Regex = Regex::new(OS_IMG_FTYPE_REGEX).unwrap();
-            static ref INITRD_FTYPE_RE: Regex = Regex::new(INITRD_FTYPE_REGEX).unwrap();
-            static ref OS_CFG_FTYPE_RE: Regex = Regex::new(OS_CFG_FTYPE_REGEX).unwrap();
-            static ref TEXT_FTYPE_RE: Regex = Regex::new(TEXT_FTYPE_REGEX).unwrap();
-            static ref KERNEL_AMD64_FTYPE_RE: Regex = Regex::new(KERNEL_AMD64_FTYPE_REGEX).unwrap();
-            static ref KERNEL_ARMHF_FTYPE_RE: Regex = Regex::new(KERNEL_ARMHF_FTYPE_REGEX).unwrap();
-            static ref KERNEL_I386_FTYPE_RE: Regex = Regex::new(KERNEL_I386_FTYPE_REGEX).unwrap();
-            static ref DTB_FTYPE_RE: Regex = Regex::new(DTB_FTYPE_REGEX).unwrap();
+        let sig_str = signature_path.to_string_lossy();
+        let path_str = self.path.to_string_lossy();
+        let verify_res = call(GPG_CMD, &["--verify", &sig_str, &path_str], true)?;
+        if !verify_res.status.success() {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!(
+                    "verify_signature: signature verification failed for '{}': {}",
+                    self.path.display(),
+                    verify_res.stderr
+                ),
+            ));
         }
 
-        debug!(
-            "FileInfo::is_type: looking for: {}, found {}",
-            ftype.get_descr(),
-            cmd_res.stdout
-        );
-        match ftype {
-            FileType::OSImage => Ok(OS_IMG_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::InitRD => Ok(INITRD_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::KernelARMHF => Ok(KERNEL_ARMHF_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::KernelAMD64 => Ok(KERNEL_AMD64_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::KernelI386 => Ok(KERNEL_I386_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::Json => Ok(OS_CFG_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::Text => Ok(TEXT_FTYPE_RE.is_match(&cmd_res.stdout)),
-            FileType::DTB => Ok(DTB_FTYPE_RE.is_match(&cmd_res.stdout)),
-        }
+        Ok(())
     }
 
-    #[cfg(target_os = "windows")]
-    pub fn expect_type(&self, ftype: &FileType) -> Result<(), MigError> {
-        if !self.is_type(ftype)? {
-            let message = format!(
-                "Could not determine expected file type '{}' for file '{}'",
-                ftype.get_descr(),
-                self.path.display()
-            );
-            error!("{}", message);
-            Err(MigError::from_remark(MigErrorKind::InvParam, &message))
-        } else {
-            Ok(())
+    /// Run `mode`'s checks against `self` and, only once they all pass,
+    /// hand back a `Verified` wrapper. `expected_sha256` is required
+    /// unless `mode` is `Off`; `signature` (public key, signature file) is
+    /// required only for `ChecksumAndSignature`.
+    pub fn verify(
+        self,
+        mode: VerifyMode,
+        expected_sha256: Option<&str>,
+        signature: Option<(&Path, &Path)>,
+    ) -> Result<Verified, MigError> {
+        if mode != VerifyMode::Off {
+            let expected = expected_sha256.ok_or_else(|| {
+                MigError::from_remark(
+                    MigErrorKind::InvParam,
+                    "verify: checksum verification requires an expected SHA-256 digest",
+                )
+            })?;
+
+            if !self.verify_sha256(expected)? {
+                return Err(MigError::from_remark(
+                    MigErrorKind::InvParam,
+                    &format!("verify: SHA-256 mismatch for '{}'", self.path.display()),
+                ));
+            }
         }
-    }
 
-    #[cfg(target_os = "windows")]
-    pub fn is_type(&self, ftype: &FileType) -> Result<bool, MigError> {
-        // think of something for windows
-        Err(MigError::from(MigErrorKind::NotImpl))
+        if mode == VerifyMode::ChecksumAndSignature {
+            let (public_key_path, signature_path) = signature.ok_or_else(|| {
+                MigError::from_remark(
+                    MigErrorKind::InvParam,
+                    "verify: checksum+signature verification requires a public key and a signature file",
+                )
+            })?;
+            self.verify_signature(public_key_path, signature_path)?;
+        }
+
+        Ok(Verified { info: self })
     }
 }
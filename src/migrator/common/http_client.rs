@@ -0,0 +1,416 @@
+// Minimal, dependency-light HTTP/1.1 client used for balena API/VPN reachability
+// checks. We don't want to pull in a full async HTTP stack just to issue a
+// handful of GETs during `check()`, so this talks raw HTTP/1.1 over a
+// `TcpStream` (and, behind the `https` feature, a TLS stream layered on top).
+
+use log::{debug, trace};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use url::Url;
+
+use crate::common::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+#[cfg(feature = "https")]
+use native_tls::TlsConnector;
+
+const MODULE: &str = "common::http_client";
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+}
+
+/// Issue `GET path` against `url` (scheme://host[:port]), optionally sending
+/// `Authorization: Bearer <token>`, and return the parsed status + body.
+pub(crate) fn http_get(
+    url: &str,
+    path: &str,
+    bearer: Option<&str>,
+    timeout: u64,
+) -> Result<HttpResponse, MigError> {
+    let parsed = Url::parse(url).context(MigErrCtx::from_remark(
+        MigErrorKind::InvParam,
+        &format!("{}::http_get: invalid url: '{}'", MODULE, url),
+    ))?;
+
+    let host = parsed.host_str().ok_or_else(|| {
+        MigError::from_remark(
+            MigErrorKind::InvParam,
+            &format!("{}::http_get: url has no host: '{}'", MODULE, url),
+        )
+    })?;
+
+    let https = match parsed.scheme() {
+        "http" => false,
+        "https" => true,
+        scheme => {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::http_get: unsupported scheme: '{}'", MODULE, scheme),
+            ));
+        }
+    };
+
+    let port = parsed.port().unwrap_or(if https { 443 } else { 80 });
+
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: balena-migrate\r\n",
+        path, host
+    );
+
+    if let Some(token) = bearer {
+        request += &format!("Authorization: Bearer {}\r\n", token);
+    }
+
+    request += "\r\n";
+
+    trace!("{}::http_get: connecting to '{}:{}'", MODULE, host, port);
+
+    use std::net::ToSocketAddrs;
+    let sock_addr = (host, port)
+        .to_socket_addrs()
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::http_get: failed to resolve '{}:{}'", MODULE, host, port),
+        ))?
+        .next()
+        .ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!("{}::http_get: no address found for '{}'", MODULE, host),
+            )
+        })?;
+
+    let tcp_stream = TcpStream::connect_timeout(&sock_addr, Duration::from_secs(timeout)).context(
+        MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::http_get: failed to connect to '{}:{}'", MODULE, host, port),
+        ),
+    )?;
+
+    tcp_stream
+        .set_read_timeout(Some(Duration::from_secs(timeout)))
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::http_get: failed to set read timeout", MODULE),
+        ))?;
+
+    if https {
+        #[cfg(feature = "https")]
+        {
+            let connector = TlsConnector::new().context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::http_get: failed to create TLS connector", MODULE),
+            ))?;
+            let mut tls_stream = connector.connect(host, tcp_stream).context(
+                MigErrCtx::from_remark(
+                    MigErrorKind::Upstream,
+                    &format!("{}::http_get: TLS handshake with '{}' failed", MODULE, host),
+                ),
+            )?;
+            return send_and_read(&mut tls_stream, &request);
+        }
+        #[cfg(not(feature = "https"))]
+        {
+            return Err(MigError::from_remark(
+                MigErrorKind::NotImpl,
+                &format!(
+                    "{}::http_get: https endpoint '{}' requires the 'https' feature",
+                    MODULE, url
+                ),
+            ));
+        }
+    }
+
+    let mut tcp_stream = tcp_stream;
+    send_and_read(&mut tcp_stream, &request)
+}
+
+/// Stream `GET path` against `url` straight to `dest`, rather than buffering
+/// the body in memory - used to fetch multi-gigabyte OS images.
+pub(crate) fn download_to_file(
+    url: &str,
+    path: &str,
+    dest: &mut std::fs::File,
+    timeout: u64,
+) -> Result<(), MigError> {
+    let parsed = Url::parse(url).context(MigErrCtx::from_remark(
+        MigErrorKind::InvParam,
+        &format!("{}::download_to_file: invalid url: '{}'", MODULE, url),
+    ))?;
+
+    let host = parsed.host_str().ok_or_else(|| {
+        MigError::from_remark(
+            MigErrorKind::InvParam,
+            &format!("{}::download_to_file: url has no host: '{}'", MODULE, url),
+        )
+    })?;
+
+    let https = match parsed.scheme() {
+        "http" => false,
+        "https" => true,
+        scheme => {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::download_to_file: unsupported scheme: '{}'", MODULE, scheme),
+            ));
+        }
+    };
+
+    let port = parsed.port().unwrap_or(if https { 443 } else { 80 });
+
+    use std::net::ToSocketAddrs;
+    let sock_addr = (host, port)
+        .to_socket_addrs()
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::download_to_file: failed to resolve '{}:{}'", MODULE, host, port),
+        ))?
+        .next()
+        .ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!("{}::download_to_file: no address found for '{}'", MODULE, host),
+            )
+        })?;
+
+    let tcp_stream = TcpStream::connect_timeout(&sock_addr, Duration::from_secs(timeout)).context(
+        MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::download_to_file: failed to connect to '{}:{}'", MODULE, host, port),
+        ),
+    )?;
+    tcp_stream
+        .set_read_timeout(Some(Duration::from_secs(timeout)))
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::download_to_file: failed to set read timeout", MODULE),
+        ))?;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: balena-migrate\r\n\r\n",
+        path, host
+    );
+
+    if https {
+        #[cfg(feature = "https")]
+        {
+            let connector = TlsConnector::new().context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::download_to_file: failed to create TLS connector", MODULE),
+            ))?;
+            let mut tls_stream = connector.connect(host, tcp_stream).context(
+                MigErrCtx::from_remark(
+                    MigErrorKind::Upstream,
+                    &format!("{}::download_to_file: TLS handshake with '{}' failed", MODULE, host),
+                ),
+            )?;
+            return stream_to_file(&mut tls_stream, &request, dest, url);
+        }
+        #[cfg(not(feature = "https"))]
+        {
+            return Err(MigError::from_remark(
+                MigErrorKind::NotImpl,
+                &format!(
+                    "{}::download_to_file: https endpoint '{}' requires the 'https' feature",
+                    MODULE, url
+                ),
+            ));
+        }
+    }
+
+    let mut tcp_stream = tcp_stream;
+    stream_to_file(&mut tcp_stream, &request, dest, url)
+}
+
+/// Send `request` over `stream`, then read (and discard) the header block
+/// and copy everything after it straight through to `dest`.
+fn stream_to_file<S: Read + Write>(
+    stream: &mut S,
+    request: &str,
+    dest: &mut std::fs::File,
+    url: &str,
+) -> Result<(), MigError> {
+    stream
+        .write_all(request.as_bytes())
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::download_to_file: failed to write request", MODULE),
+        ))?;
+
+    let mut header_buf: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let mut status: Option<u16> = None;
+
+    loop {
+        let read = stream.read(&mut chunk).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::download_to_file: failed to read response headers", MODULE),
+        ))?;
+        if read == 0 {
+            break;
+        }
+        header_buf.extend_from_slice(&chunk[..read]);
+
+        if let Some(header_end) = find_header_end(&header_buf) {
+            let header_str = String::from_utf8_lossy(&header_buf[..header_end]).to_string();
+            status = header_str
+                .lines()
+                .next()
+                .and_then(|line| line.split_whitespace().nth(1))
+                .and_then(|code| code.parse().ok());
+
+            dest.write_all(&header_buf[header_end + 4..])
+                .context(MigErrCtx::from_remark(
+                    MigErrorKind::Upstream,
+                    &format!("{}::download_to_file: failed to write body to destination", MODULE),
+                ))?;
+            break;
+        }
+    }
+
+    match status {
+        Some(code) if (200..300).contains(&code) => (),
+        Some(code) => {
+            return Err(MigError::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::download_to_file: server responded with status {}", MODULE, code),
+            ));
+        }
+        None => {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!("{}::download_to_file: no valid response from '{}'", MODULE, url),
+            ));
+        }
+    }
+
+    std::io::copy(stream, dest).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::download_to_file: failed to stream response body", MODULE),
+    ))?;
+
+    Ok(())
+}
+
+fn send_and_read<S: Read + Write>(stream: &mut S, request: &str) -> Result<HttpResponse, MigError> {
+    stream
+        .write_all(request.as_bytes())
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::send_and_read: failed to write request", MODULE),
+        ))?;
+
+    let mut raw: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut buf).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::send_and_read: failed to read response", MODULE),
+        ))?;
+
+        if read == 0 {
+            break;
+        }
+
+        raw.extend_from_slice(&buf[..read]);
+
+        if raw.len() >= MAX_HEADER_BYTES && find_header_end(&raw).is_none() {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!("{}::send_and_read: response headers too large", MODULE),
+            ));
+        }
+
+        if let Some(header_end) = find_header_end(&raw) {
+            let headers = String::from_utf8_lossy(&raw[..header_end]).to_string();
+            let content_length = parse_content_length(&headers);
+            let body_so_far = raw.len() - (header_end + 4);
+
+            if let Some(expected) = content_length {
+                if body_so_far >= expected {
+                    break;
+                }
+            }
+        }
+    }
+
+    parse_response(&raw)
+}
+
+fn find_header_end(raw: &[u8]) -> Option<usize> {
+    raw.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+fn parse_content_length(headers: &str) -> Option<usize> {
+    for line in headers.lines() {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                return value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    None
+}
+
+fn parse_response(raw: &[u8]) -> Result<HttpResponse, MigError> {
+    let header_end = find_header_end(raw).ok_or_else(|| {
+        MigError::from_remark(
+            MigErrorKind::InvState,
+            &format!("{}::parse_response: no end of headers found in response", MODULE),
+        )
+    })?;
+
+    let header_str = String::from_utf8_lossy(&raw[..header_end]).to_string();
+    let mut lines = header_str.lines();
+
+    let status_line = lines.next().ok_or_else(|| {
+        MigError::from_remark(
+            MigErrorKind::InvState,
+            &format!("{}::parse_response: empty status line", MODULE),
+        )
+    })?;
+
+    let status: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!(
+                    "{}::parse_response: malformed status line: '{}'",
+                    MODULE, status_line
+                ),
+            )
+        })?
+        .parse()
+        .context(MigErrCtx::from_remark(
+            MigErrorKind::InvState,
+            &format!(
+                "{}::parse_response: non numeric status code in: '{}'",
+                MODULE, status_line
+            ),
+        ))?;
+
+    let body = String::from_utf8_lossy(&raw[header_end + 4..]).to_string();
+
+    debug!(
+        "{}::parse_response: status: {}, body len: {}",
+        MODULE,
+        status,
+        body.len()
+    );
+
+    Ok(HttpResponse { status, body })
+}
@@ -0,0 +1,237 @@
+// Pure-Rust magic-byte file type detection for `FileInfo`, replacing the
+// `file` command + locale-dependent regex matching of its prose output.
+// Inspecting the bytes directly means `FileType` detection works
+// identically on Linux and Windows instead of Windows falling back to
+// `NotImpl`.
+
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::common::{MigError, MigErrorKind};
+
+use super::file_info::FileType;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const MBR_BOOT_SIG_OFFSET: usize = 0x1FE;
+const MBR_BOOT_SIG: [u8; 2] = [0x55, 0xAA];
+const CPIO_NEWC_MAGIC: &[u8; 6] = b"070701";
+const CPIO_OLD_MAGIC: &[u8; 6] = b"070707";
+const BZIMAGE_SIG_OFFSET: usize = 0x202;
+const BZIMAGE_SIG: &[u8; 4] = b"HdrS";
+const ZIMAGE_MAGIC_OFFSET: usize = 0x24;
+const ZIMAGE_MAGIC: u32 = 0x016F_2818;
+const DTB_MAGIC: [u8; 4] = [0xD0, 0x0D, 0xFE, 0xED];
+
+const XZ_MAGIC: [u8; 6] = [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+const HEAD_SAMPLE_SIZE: usize = 4096;
+const INFLATE_SAMPLE_SIZE: usize = 1024;
+
+/// The compression codec (if any) wrapping an OS image, sniffed from its
+/// magic bytes - lets `FileInfo::open_decompressed` pick a matching
+/// streaming decoder instead of assuming gzip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompressionType {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+pub(crate) fn detect_compression(path: &Path) -> Result<CompressionType, MigError> {
+    let head = read_head(path, XZ_MAGIC.len())?;
+
+    if is_gzip(&head) {
+        Ok(CompressionType::Gzip)
+    } else if head.len() >= XZ_MAGIC.len() && head[..XZ_MAGIC.len()] == XZ_MAGIC {
+        Ok(CompressionType::Xz)
+    } else if head.len() >= ZSTD_MAGIC.len() && head[..ZSTD_MAGIC.len()] == ZSTD_MAGIC {
+        Ok(CompressionType::Zstd)
+    } else {
+        Ok(CompressionType::None)
+    }
+}
+
+/// Does `path`'s content match `ftype`'s magic bytes (and, for gzip-wrapped
+/// types, the magic bytes of the inflated stream)?
+pub(crate) fn is_type(path: &Path, ftype: &FileType) -> Result<bool, MigError> {
+    match ftype {
+        FileType::Json => is_json(path),
+        FileType::Text => Ok(is_text(&read_head(path, HEAD_SAMPLE_SIZE)?)),
+        FileType::DTB => Ok(is_dtb(&read_head(path, DTB_MAGIC.len())?)),
+        FileType::KernelAMD64 | FileType::KernelI386 => {
+            Ok(is_bzimage(&read_head(path, BZIMAGE_SIG_OFFSET + BZIMAGE_SIG.len())?))
+        }
+        FileType::KernelARMHF => Ok(is_zimage(&read_head(path, ZIMAGE_MAGIC_OFFSET + 4)?)),
+        FileType::OSImage => {
+            let head = read_head(path, GZIP_MAGIC.len())?;
+            if !is_gzip(&head) {
+                return Ok(false);
+            }
+            let inflated = inflate_head(path)?;
+            Ok(inflated.len() >= MBR_BOOT_SIG_OFFSET + MBR_BOOT_SIG.len()
+                && inflated[MBR_BOOT_SIG_OFFSET..MBR_BOOT_SIG_OFFSET + 2] == MBR_BOOT_SIG)
+        }
+        FileType::InitRD => {
+            let head = read_head(path, GZIP_MAGIC.len())?;
+            if !is_gzip(&head) {
+                return Ok(false);
+            }
+            let inflated = inflate_head(path)?;
+            Ok(inflated.len() >= CPIO_NEWC_MAGIC.len()
+                && (&inflated[..CPIO_NEWC_MAGIC.len()] == CPIO_NEWC_MAGIC
+                    || &inflated[..CPIO_OLD_MAGIC.len()] == CPIO_OLD_MAGIC))
+        }
+    }
+}
+
+fn read_head(path: &Path, len: usize) -> Result<Vec<u8>, MigError> {
+    let mut file = File::open(path).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("magic::read_head: failed to open '{}': {}", path.display(), why),
+        )
+    })?;
+
+    let mut buf = vec![0u8; len];
+    let read = file.read(&mut buf).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("magic::read_head: failed to read '{}': {}", path.display(), why),
+        )
+    })?;
+    buf.truncate(read);
+    Ok(buf)
+}
+
+/// Inflate a leading sample of the gzip stream at `path` - enough to look
+/// for a wrapped payload's own magic bytes without decompressing (and
+/// allocating for) the whole, possibly multi-gigabyte, stream. Running out
+/// of input before `INFLATE_SAMPLE_SIZE` bytes are produced is expected and
+/// not an error: whatever was inflated so far is still returned.
+fn inflate_head(path: &Path) -> Result<Vec<u8>, MigError> {
+    let file = File::open(path).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("magic::inflate_head: failed to open '{}': {}", path.display(), why),
+        )
+    })?;
+
+    let mut decoder = GzDecoder::new(file);
+    let mut buf = vec![0u8; INFLATE_SAMPLE_SIZE];
+    let mut total = 0;
+    while total < buf.len() {
+        match decoder.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(read) => total += read,
+            Err(_) => break,
+        }
+    }
+    buf.truncate(total);
+    Ok(buf)
+}
+
+fn is_gzip(head: &[u8]) -> bool {
+    head.len() >= GZIP_MAGIC.len() && head[..GZIP_MAGIC.len()] == GZIP_MAGIC
+}
+
+fn is_bzimage(head: &[u8]) -> bool {
+    head.len() >= BZIMAGE_SIG_OFFSET + BZIMAGE_SIG.len()
+        && &head[BZIMAGE_SIG_OFFSET..BZIMAGE_SIG_OFFSET + BZIMAGE_SIG.len()] == BZIMAGE_SIG
+}
+
+fn is_zimage(head: &[u8]) -> bool {
+    head.len() >= ZIMAGE_MAGIC_OFFSET + 4
+        && u32::from_le_bytes([
+            head[ZIMAGE_MAGIC_OFFSET],
+            head[ZIMAGE_MAGIC_OFFSET + 1],
+            head[ZIMAGE_MAGIC_OFFSET + 2],
+            head[ZIMAGE_MAGIC_OFFSET + 3],
+        ]) == ZIMAGE_MAGIC
+}
+
+fn is_dtb(head: &[u8]) -> bool {
+    head.len() >= DTB_MAGIC.len() && head[..DTB_MAGIC.len()] == DTB_MAGIC
+}
+
+fn is_text(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(sample) => sample
+            .chars()
+            .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t')),
+        Err(_) => false,
+    }
+}
+
+fn is_json(path: &Path) -> Result<bool, MigError> {
+    let file = File::open(path).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("magic::is_json: failed to open '{}': {}", path.display(), why),
+        )
+    })?;
+    Ok(serde_json::from_reader::<_, Value>(file).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_magic_at_offset_zero() {
+        assert!(is_gzip(&[0x1F, 0x8B, 0x08, 0x00]));
+        assert!(!is_gzip(&[0x1F, 0x8C, 0x08, 0x00]));
+        assert!(!is_gzip(&[0x1F]));
+    }
+
+    #[test]
+    fn bzimage_signature_at_0x202() {
+        let mut head = vec![0u8; BZIMAGE_SIG_OFFSET + BZIMAGE_SIG.len()];
+        head[BZIMAGE_SIG_OFFSET..].copy_from_slice(BZIMAGE_SIG);
+        assert!(is_bzimage(&head));
+
+        let too_short = vec![0u8; BZIMAGE_SIG_OFFSET];
+        assert!(!is_bzimage(&too_short));
+
+        let mut wrong = vec![0u8; BZIMAGE_SIG_OFFSET + BZIMAGE_SIG.len()];
+        wrong[BZIMAGE_SIG_OFFSET..].copy_from_slice(b"Nope");
+        assert!(!is_bzimage(&wrong));
+    }
+
+    #[test]
+    fn zimage_magic_at_0x24_is_little_endian() {
+        let mut head = vec![0u8; ZIMAGE_MAGIC_OFFSET + 4];
+        head[ZIMAGE_MAGIC_OFFSET..ZIMAGE_MAGIC_OFFSET + 4]
+            .copy_from_slice(&ZIMAGE_MAGIC.to_le_bytes());
+        assert!(is_zimage(&head));
+
+        let too_short = vec![0u8; ZIMAGE_MAGIC_OFFSET];
+        assert!(!is_zimage(&too_short));
+    }
+
+    #[test]
+    fn dtb_magic() {
+        assert!(is_dtb(&DTB_MAGIC));
+        assert!(!is_dtb(&[0x00, 0x00, 0x00, 0x00]));
+        assert!(!is_dtb(&[0xD0, 0x0D]));
+    }
+
+    #[test]
+    fn text_accepts_plain_ascii_and_common_whitespace() {
+        assert!(is_text(b"hello\nworld\r\n\ttabbed"));
+    }
+
+    #[test]
+    fn text_rejects_binary_control_bytes() {
+        assert!(!is_text(&[0x00, 0x01, 0x02]));
+    }
+
+    #[test]
+    fn text_rejects_invalid_utf8() {
+        assert!(!is_text(&[0xFF, 0xFE]));
+    }
+}
@@ -0,0 +1,145 @@
+// Native, std::error::Error-based replacement for the (now unmaintained)
+// `failure` crate. A `MigError` carries a `kind`, an optional boxed source
+// error and a stack of human-readable context strings pushed on with
+// `.context(...)` - `Display` renders the full chain, innermost cause last,
+// so a failed `df` or TCP connect prints the operation that triggered it as
+// well as the underlying OS error instead of a generic "Upstream" remark.
+
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MigErrorKind {
+    NotFound,
+    NotImpl,
+    InvParam,
+    InvState,
+    Upstream,
+    ExecProcess,
+    /// The error has already been logged via `error!()`, callers further up
+    /// the stack shouldn't print it again - just propagate and bail out.
+    Displayed,
+}
+
+impl Display for MigErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A pushed context frame: what was being attempted, recorded at the point
+/// the underlying operation failed.
+pub struct MigErrCtx {
+    kind: MigErrorKind,
+    remark: String,
+}
+
+impl MigErrCtx {
+    pub fn from_remark(kind: MigErrorKind, remark: &str) -> MigErrCtx {
+        MigErrCtx {
+            kind,
+            remark: String::from(remark),
+        }
+    }
+}
+
+pub struct MigError {
+    kind: MigErrorKind,
+    source: Option<Box<dyn StdError + Send + Sync + 'static>>,
+    context: Vec<String>,
+}
+
+impl MigError {
+    pub fn from_remark(kind: MigErrorKind, remark: &str) -> MigError {
+        MigError {
+            kind,
+            source: None,
+            context: vec![String::from(remark)],
+        }
+    }
+
+    pub fn from_kind(kind: MigErrorKind) -> MigError {
+        MigError {
+            kind,
+            source: None,
+            context: Vec::new(),
+        }
+    }
+
+    /// Shorthand for an error that has already been reported via `error!()`
+    /// at the point of failure - the caller just needs to propagate it.
+    pub fn displayed() -> MigError {
+        MigError::from_kind(MigErrorKind::Displayed)
+    }
+
+    pub fn kind(&self) -> MigErrorKind {
+        self.kind
+    }
+
+    /// Push a new context frame onto this error, e.g. to record the
+    /// operation that was being attempted one level further up the stack.
+    pub fn context(mut self, ctx: MigErrCtx) -> MigError {
+        self.kind = ctx.kind;
+        self.context.push(ctx.remark);
+        self
+    }
+}
+
+impl From<MigErrorKind> for MigError {
+    fn from(kind: MigErrorKind) -> MigError {
+        MigError::from_kind(kind)
+    }
+}
+
+impl fmt::Debug for MigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for MigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "[{}]", self.kind)?;
+        // context frames are pushed innermost-first (closest to the call
+        // site that attached them), so render outermost-first, innermost
+        // cause last, followed by the original source error if we have one
+        for ctx in self.context.iter().rev() {
+            write!(f, " {}", ctx)?;
+        }
+        if let Some(ref source) = self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for MigError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as &(dyn StdError + 'static))
+    }
+}
+
+/// Mirrors `failure::ResultExt::context` so call sites only need to change
+/// their `use` statement, not every `.context(...)` call site.
+pub trait ResultExt<T> {
+    fn context(self, ctx: MigErrCtx) -> Result<T, MigError>;
+}
+
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn context(self, ctx: MigErrCtx) -> Result<T, MigError> {
+        self.map_err(|err| MigError {
+            kind: ctx.kind,
+            source: Some(Box::new(err)),
+            context: vec![ctx.remark],
+        })
+    }
+}
+
+impl<T> ResultExt<T> for Result<T, MigError> {
+    fn context(self, ctx: MigErrCtx) -> Result<T, MigError> {
+        self.map_err(|err| err.context(ctx))
+    }
+}
@@ -1,21 +1,24 @@
 use crate::{
     common::{
         check_tcp_connect, file_info::RelFileInfo, Config, FileInfo, MigErrCtx, MigError,
-        MigErrorKind,
+        MigErrorKind, ResultExt,
     },
     defs::BALENA_API_PORT,
 };
 
+use crate::common::http_client::http_get;
 use crate::linux::linux_common::mktemp;
-use failure::ResultExt;
+use fatfs::{FileSystem, FsOptions};
 use log::{error, info};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::BufReader;
+use std::io::{BufReader, Write};
 use std::path::{Path, PathBuf};
 use url::Url;
 
+const BOOT_CFG_FILE: &str = "config.json";
+
 #[derive(Debug, Clone)]
 pub(crate) struct BalenaCfgJson {
     config: HashMap<String, Value>,
@@ -45,6 +48,8 @@ impl BalenaCfgJson {
     }
 
     pub fn write(&mut self, work_dir: &Path) -> Result<PathBuf, MigError> {
+        self.validate_network_config()?;
+
         let new_path = mktemp(false, Some("config.json"), Some(work_dir.to_path_buf())).context(
             MigErrCtx::from_remark(MigErrorKind::Upstream, "Failed to create temporary file"),
         )?;
@@ -71,10 +76,91 @@ impl BalenaCfgJson {
         Ok(new_path)
     }
 
+    /// Write the (possibly modified) config directly into the root directory of a
+    /// FAT32 boot partition image/device, without mounting it. `boot_part` is
+    /// opened read/write; an existing `config.json` in its root directory is
+    /// truncated and overwritten in place, and one is created if absent.
+    pub fn write_to_boot_partition(&mut self, boot_part: &Path) -> Result<(), MigError> {
+        self.validate_network_config()?;
+
+        let partition_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(boot_part)
+            .context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!(
+                    "write_to_boot_partition: failed to open boot partition '{}'",
+                    boot_part.display()
+                ),
+            ))?;
+
+        let fs = FileSystem::new(partition_file, FsOptions::new()).context(
+            MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!(
+                    "write_to_boot_partition: failed to open FAT filesystem on '{}'",
+                    boot_part.display()
+                ),
+            ),
+        )?;
+
+        let root_dir = fs.root_dir();
+        let mut cfg_file = root_dir
+            .create_file(BOOT_CFG_FILE)
+            .context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!(
+                    "write_to_boot_partition: failed to open '{}' in boot partition '{}'",
+                    BOOT_CFG_FILE,
+                    boot_part.display()
+                ),
+            ))?;
+
+        cfg_file.truncate().context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "write_to_boot_partition: failed to truncate '{}'",
+                BOOT_CFG_FILE
+            ),
+        ))?;
+
+        let buf = serde_json::to_vec(&self.config).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            "write_to_boot_partition: failed to serialize config.json",
+        ))?;
+
+        cfg_file.write_all(&buf).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "write_to_boot_partition: failed to write '{}' into boot partition '{}'",
+                BOOT_CFG_FILE,
+                boot_part.display()
+            ),
+        ))?;
+
+        self.modified = false;
+
+        info!(
+            "wrote config.json directly into boot partition '{}'",
+            boot_part.display()
+        );
+
+        Ok(())
+    }
+
     pub fn check(&self, config: &Config) -> Result<(), MigError> {
         // TODO: app_name is not checked
         info!("Configured for application id: {}", self.get_app_id()?);
 
+        if (config.is_check_api() || config.is_check_vpn()) && !self.has_network_config() {
+            log::warn!(
+                "API/VPN checks were requested but no first-boot network config (wifi/static \
+                 ethernet) is present in config.json - if this device has no wired link it may \
+                 never come online after flashing"
+            );
+        }
+
         if config.is_check_api() {
             let api_endpoint = &self.get_api_endpoint()?;
 
@@ -100,6 +186,58 @@ impl BalenaCfgJson {
                     );
                     return Err(MigError::displayed());
                 }
+
+                // a bare TCP connect only proves something is listening on the port - a
+                // firewall or TLS-terminating proxy can accept the connection and still
+                // reject the credentials, so actually call the API before declaring victory
+                match http_get(api_endpoint, "/ping", None, config.get_check_timeout()) {
+                    Ok(response) => {
+                        if response.is_success() {
+                            info!("balena api ping on {} succeeded", api_endpoint);
+                        } else {
+                            error!(
+                                "balena api ping on {} failed with status {}: {}",
+                                api_endpoint, response.status, response.body
+                            );
+                            return Err(MigError::displayed());
+                        }
+                    }
+                    Err(why) => {
+                        error!(
+                            "failed to reach balena api on {}: {:?}, your device might not come online",
+                            api_endpoint, why
+                        );
+                        return Err(MigError::displayed());
+                    }
+                }
+
+                if let Ok(api_key) = self.get_api_key() {
+                    match http_get(
+                        api_endpoint,
+                        "/v6/device",
+                        Some(&api_key),
+                        config.get_check_timeout(),
+                    ) {
+                        Ok(response) => {
+                            if response.is_success() {
+                                info!("authenticated balena api check on {} succeeded", api_endpoint);
+                            } else {
+                                error!(
+                                    "authenticated balena api check on {} failed with status {}: {}",
+                                    api_endpoint, response.status, response.body
+                                );
+                                return Err(MigError::displayed());
+                            }
+                        }
+                        Err(why) => {
+                            error!(
+                                "failed to authenticate against balena api on {}: {:?}",
+                                api_endpoint, why
+                            );
+                            return Err(MigError::displayed());
+                        }
+                    }
+                }
             } else {
                 error!(
                     "failed to parse api server url from config.json: {}",
@@ -113,7 +251,9 @@ impl BalenaCfgJson {
             let vpn_endpoint = self.get_vpn_endpoint()?;
             let vpn_port = self.get_vpn_port()? as u16;
             if let Ok(_v) = check_tcp_connect(&vpn_endpoint, vpn_port, config.get_check_timeout()) {
-                // TODO: call a command on API instead of just connecting
+                // the VPN endpoint doesn't speak HTTP, so a TCP connect is the best
+                // reachability signal we can get here; the API endpoint above is checked
+                // at the HTTP level instead since it can tell us more
                 info!("connection to vpn: {}:{} is ok", vpn_endpoint, vpn_port);
             } else {
                 error!(
@@ -221,4 +361,174 @@ impl BalenaCfgJson {
     pub fn get_device_type(&self) -> Result<String, MigError> {
         self.get_str_val("deviceType")
     }
+
+    /// Merge a NetworkManager-style WiFi connection definition into `os.network.connections`
+    /// so the flashed balenaOS comes up on the given SSID on first boot.
+    pub fn set_wifi(&mut self, ssid: &str, psk: &str) -> Result<(), MigError> {
+        let conn = serde_json::json!({
+            "connection": { "id": ssid, "type": "wifi" },
+            "wifi": { "ssid": ssid, "mode": "infrastructure" },
+            "wifi-security": { "key-mgmt": "wpa-psk", "psk": psk },
+            "ipv4": { "method": "auto" },
+            "ipv6": { "method": "auto" },
+        });
+
+        self.merge_network_connection(format!("resin-wifi-{}", ssid), conn)
+    }
+
+    /// Merge a static Ethernet connection definition into `os.network.connections`.
+    /// `netmask` is a dotted-quad subnet mask (e.g. `255.255.255.0`); NetworkManager's
+    /// keyfile `address1` wants a CIDR prefix length instead, so it's converted here.
+    pub fn set_static_ethernet(
+        &mut self,
+        address: &str,
+        netmask: &str,
+        gateway: &str,
+    ) -> Result<(), MigError> {
+        let prefix = netmask_to_prefix(netmask)?;
+
+        let conn = serde_json::json!({
+            "connection": { "id": "resin-ethernet-static", "type": "ethernet" },
+            "ipv4": {
+                "method": "manual",
+                "address1": format!("{}/{},{}", address, prefix, gateway),
+            },
+        });
+
+        self.merge_network_connection(String::from("resin-ethernet-static"), conn)
+    }
+
+    /// Merge an `os.proxy` block into the config so the flashed balenaOS can
+    /// reach the balena API/VPN through an HTTP(S) proxy on first boot.
+    pub fn set_proxy(
+        &mut self,
+        proxy_type: &str,
+        address: &str,
+        port: u16,
+        login: Option<(&str, &str)>,
+    ) -> Result<(), MigError> {
+        let mut proxy = serde_json::json!({
+            "type": proxy_type,
+            "ip": address,
+            "port": port,
+        });
+
+        if let Some((username, password)) = login {
+            proxy["login"] = Value::String(username.to_string());
+            proxy["password"] = Value::String(password.to_string());
+        }
+
+        let os_section = self
+            .config
+            .entry(String::from("os"))
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+
+        os_section
+            .as_object_mut()
+            .ok_or_else(|| {
+                MigError::from_remark(
+                    MigErrorKind::InvParam,
+                    "set_proxy: 'os' key in config.json is not an object",
+                )
+            })?
+            .insert(String::from("proxy"), proxy);
+
+        self.modified = true;
+        Ok(())
+    }
+
+    fn merge_network_connection(&mut self, id: String, conn: Value) -> Result<(), MigError> {
+        let os_section = self
+            .config
+            .entry(String::from("os"))
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+
+        let os_section = os_section.as_object_mut().ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvParam,
+                "merge_network_connection: 'os' key in config.json is not an object",
+            )
+        })?;
+
+        let network_section = os_section
+            .entry(String::from("network"))
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+
+        let connections = network_section
+            .as_object_mut()
+            .ok_or_else(|| {
+                MigError::from_remark(
+                    MigErrorKind::InvParam,
+                    "merge_network_connection: 'os.network' key in config.json is not an object",
+                )
+            })?
+            .entry(String::from("connections"))
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+
+        connections
+            .as_object_mut()
+            .ok_or_else(|| {
+                MigError::from_remark(
+                    MigErrorKind::InvParam,
+                    "merge_network_connection: 'os.network.connections' is not an object",
+                )
+            })?
+            .insert(id, conn);
+
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Make sure a proxy block set via `set_proxy` carries its required sub-keys
+    /// before the config is written out.
+    fn validate_network_config(&self) -> Result<(), MigError> {
+        if let Some(proxy) = self.config.get("os").and_then(|os| os.get("proxy")) {
+            for key in &["type", "ip", "port"] {
+                if proxy.get(*key).is_none() {
+                    return Err(MigError::from_remark(
+                        MigErrorKind::InvParam,
+                        &format!("validate_network_config: 'os.proxy' is missing required key '{}'", key),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// True if the config has at least one first-boot network connection defined.
+    pub fn has_network_config(&self) -> bool {
+        self.config
+            .get("os")
+            .and_then(|os| os.get("network"))
+            .and_then(|network| network.get("connections"))
+            .and_then(|connections| connections.as_object())
+            .map(|connections| !connections.is_empty())
+            .unwrap_or(false)
+    }
+}
+
+/// Convert a dotted-quad subnet mask (e.g. `255.255.255.0`) to its CIDR
+/// prefix length (e.g. `24`), rejecting masks whose set bits aren't a
+/// contiguous run from the most significant bit.
+fn netmask_to_prefix(netmask: &str) -> Result<u32, MigError> {
+    let addr: std::net::Ipv4Addr = netmask.parse().map_err(|_| {
+        MigError::from_remark(
+            MigErrorKind::InvParam,
+            &format!("netmask_to_prefix: invalid netmask '{}'", netmask),
+        )
+    })?;
+
+    let bits = u32::from(addr);
+    let prefix = bits.leading_ones();
+    if bits.checked_shl(prefix).unwrap_or(0) != 0 {
+        return Err(MigError::from_remark(
+            MigErrorKind::InvParam,
+            &format!(
+                "netmask_to_prefix: '{}' is not a contiguous subnet mask",
+                netmask
+            ),
+        ));
+    }
+
+    Ok(prefix)
 }
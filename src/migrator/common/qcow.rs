@@ -0,0 +1,275 @@
+// Transparent raw view over a qcow2 image, so the existing flash path can
+// stream sectors from a qcow2 file the same way it streams from a raw image,
+// without shelling out to `qemu-img convert` first.
+
+use log::{debug, trace};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "common::qcow";
+
+pub(crate) const QCOW_MAGIC: [u8; 4] = [0x51, 0x46, 0x49, 0xFB]; // "QFI\xFB"
+
+/// Parsed qcow2 header fields we need to translate guest offsets to file
+/// offsets. See the qcow2 spec for the full layout - we only read what's
+/// required to walk the L1/L2 tables.
+#[derive(Debug)]
+struct QcowHeader {
+    cluster_bits: u32,
+    size: u64,
+    l1_table_offset: u64,
+    l1_size: u32,
+}
+
+/// A seekable, read-only raw view over a qcow2 file: reads are translated
+/// through the two-level L1/L2 cluster tables, with unallocated clusters
+/// returned as zero-filled regions.
+pub(crate) struct QcowFile {
+    file: File,
+    header: QcowHeader,
+    cluster_size: u64,
+    l1_table: Vec<u64>,
+    pos: u64,
+}
+
+impl QcowFile {
+    pub fn open(mut file: File) -> Result<QcowFile, MigError> {
+        let header = QcowFile::read_header(&mut file)?;
+        let cluster_size = 1u64 << header.cluster_bits;
+
+        let l1_table = QcowFile::read_l1_table(&mut file, &header)?;
+
+        Ok(QcowFile {
+            file,
+            header,
+            cluster_size,
+            l1_table,
+            pos: 0,
+        })
+    }
+
+    fn read_header(file: &mut File) -> Result<QcowHeader, MigError> {
+        file.seek(SeekFrom::Start(0)).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::read_header: failed to seek to start of file", MODULE),
+        ))?;
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::read_header: failed to read magic bytes", MODULE),
+        ))?;
+
+        if magic != QCOW_MAGIC {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::read_header: not a qcow2 file (bad magic)", MODULE),
+            ));
+        }
+
+        let mut version = [0u8; 4];
+        file.read_exact(&mut version).context(MigErrCtx::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::read_header: failed to read version", MODULE),
+        ))?;
+        let version = u32::from_be_bytes(version);
+
+        if version < 2 {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::read_header: unsupported qcow version {}", MODULE, version),
+            ));
+        }
+
+        // backing_file_offset(u64) + backing_file_size(u32)
+        file.seek(SeekFrom::Current(8 + 4))
+            .context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::read_header: failed to seek past backing file fields", MODULE),
+            ))?;
+
+        let cluster_bits = read_u32(file)?;
+        let size = read_u64(file)?;
+        let _crypt_method = read_u32(file)?;
+        let l1_size = read_u32(file)?;
+        let l1_table_offset = read_u64(file)?;
+
+        Ok(QcowHeader {
+            cluster_bits,
+            size,
+            l1_table_offset,
+            l1_size,
+        })
+    }
+
+    fn read_l1_table(file: &mut File, header: &QcowHeader) -> Result<Vec<u64>, MigError> {
+        file.seek(SeekFrom::Start(header.l1_table_offset))
+            .context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::read_l1_table: failed to seek to L1 table", MODULE),
+            ))?;
+
+        let mut table = Vec::with_capacity(header.l1_size as usize);
+        for _ in 0..header.l1_size {
+            // top bits are flags (COPIED), mask them off - we are read-only
+            table.push(read_u64(file)? & 0x00ff_ffff_ffff_fe00);
+        }
+
+        debug!("{}::read_l1_table: read {} L1 entries", MODULE, table.len());
+        Ok(table)
+    }
+
+    fn l2_entries_per_cluster(&self) -> u64 {
+        self.cluster_size / 8
+    }
+
+    /// The virtual (guest) disk size declared in the qcow2 header.
+    pub fn size(&self) -> u64 {
+        self.header.size
+    }
+
+    /// Translate a guest byte offset into the file offset of the start of
+    /// the cluster containing it, or `None` if the cluster is unallocated.
+    fn translate_cluster(&mut self, guest_offset: u64) -> Result<Option<u64>, MigError> {
+        let l2_entries = self.l2_entries_per_cluster();
+        let l1_index = (guest_offset / self.cluster_size) / l2_entries;
+        let l2_index = (guest_offset / self.cluster_size) % l2_entries;
+
+        let l2_table_offset = *self.l1_table.get(l1_index as usize).ok_or_else(|| {
+            MigError::from_remark(
+                MigErrorKind::InvParam,
+                &format!("{}::translate_cluster: L1 index {} out of range", MODULE, l1_index),
+            )
+        })?;
+
+        if l2_table_offset == 0 {
+            return Ok(None);
+        }
+
+        self.file
+            .seek(SeekFrom::Start(l2_table_offset + l2_index * 8))
+            .context(MigErrCtx::from_remark(
+                MigErrorKind::Upstream,
+                &format!("{}::translate_cluster: failed to seek to L2 entry", MODULE),
+            ))?;
+
+        let l2_entry = read_u64(&mut self.file)? & 0x00ff_ffff_ffff_fe00;
+
+        if l2_entry == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(l2_entry))
+        }
+    }
+}
+
+impl Read for QcowFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let cluster_offset_in = self.pos % self.cluster_size;
+        let to_read = std::cmp::min(buf.len() as u64, self.cluster_size - cluster_offset_in) as usize;
+
+        trace!(
+            "{}::read: pos {}, to_read {}",
+            MODULE, self.pos, to_read
+        );
+
+        match self.translate_cluster(self.pos).map_err(to_io_err)? {
+            Some(file_cluster_offset) => {
+                self.file
+                    .seek(SeekFrom::Start(file_cluster_offset + cluster_offset_in))?;
+                self.file.read_exact(&mut buf[..to_read])?;
+            }
+            None => {
+                // unallocated cluster reads back as zeroes
+                for b in buf[..to_read].iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+
+        self.pos += to_read as u64;
+        Ok(to_read)
+    }
+}
+
+impl Seek for QcowFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(offset) => self.pos = offset,
+            SeekFrom::Current(delta) => {
+                self.pos = (self.pos as i64 + delta) as u64;
+            }
+            SeekFrom::End(delta) => {
+                let end = self.header.size as i64;
+                let new_pos = end.checked_add(delta).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "seek offset overflows the virtual disk size",
+                    )
+                })?;
+                if new_pos < 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "invalid seek to a negative position",
+                    ));
+                }
+                self.pos = new_pos as u64;
+            }
+        }
+        Ok(self.pos)
+    }
+}
+
+fn read_u32(file: &mut File) -> Result<u32, MigError> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::read_u32: failed to read from qcow2 header", MODULE),
+    ))?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(file: &mut File) -> Result<u64, MigError> {
+    let mut buf = [0u8; 8];
+    file.read_exact(&mut buf).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::read_u64: failed to read from qcow2 table", MODULE),
+    ))?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn to_io_err(err: MigError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err))
+}
+
+/// Sniff the first 4 bytes of `file` to see if it's a qcow2 image.
+pub(crate) fn is_qcow2(file: &mut File) -> Result<bool, MigError> {
+    let pos = file.stream_position().context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::is_qcow2: failed to get stream position", MODULE),
+    ))?;
+
+    file.seek(SeekFrom::Start(0)).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::is_qcow2: failed to seek to start", MODULE),
+    ))?;
+
+    let mut magic = [0u8; 4];
+    let is_qcow2 = match file.read_exact(&mut magic) {
+        Ok(()) => magic == QCOW_MAGIC,
+        Err(_) => false,
+    };
+
+    file.seek(SeekFrom::Start(pos)).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::is_qcow2: failed to restore stream position", MODULE),
+    ))?;
+
+    Ok(is_qcow2)
+}
@@ -0,0 +1,125 @@
+// Garbage-collect downloaded artifacts (remote images, kernels, initramfs,
+// ...) that accumulate in `work_dir` across repeated/remote-fetch runs.
+// Borrows the "keep N most recent generations plus GC roots" idea from
+// lanzaboote's `configuration_limit`: files referenced by the active config
+// ("roots") always survive, as do the `retain` most-recently-modified
+// remaining files; everything else is pruned. Artifacts are deduplicated by
+// content hash (reusing `HashInfo`'s digest machinery) first, so identical
+// re-downloads under different names don't count twice towards `retain`.
+
+use log::info;
+use std::collections::HashSet;
+use std::fs::{read_dir, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::file_digest::sha256_hex;
+use super::{MigError, MigErrorKind};
+
+const MODULE: &str = "common::work_gc";
+
+struct Artifact {
+    path: PathBuf,
+    modified: SystemTime,
+    hash: String,
+}
+
+/// Enumerate the files directly inside `work_dir`, keep `roots` plus the
+/// `retain` most recently modified survivors (by first occurrence of a
+/// content hash, newest first), and remove the rest. Under `pretend`
+/// nothing is actually removed - the paths that would be pruned are simply
+/// logged and returned. `retain = None` means unlimited (only duplicates
+/// and nothing else get pruned).
+pub(crate) fn collect_garbage(
+    work_dir: &Path,
+    roots: &[PathBuf],
+    retain: Option<usize>,
+    pretend: bool,
+) -> Result<Vec<PathBuf>, MigError> {
+    let root_set: HashSet<PathBuf> = roots.iter().filter_map(|p| p.canonicalize().ok()).collect();
+
+    let entries = read_dir(work_dir).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "{}::collect_garbage: failed to read work_dir '{}': {}",
+                MODULE,
+                work_dir.display(),
+                why
+            ),
+        )
+    })?;
+
+    let mut artifacts = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|why| {
+            MigError::from_remark(
+                MigErrorKind::Upstream,
+                &format!(
+                    "{}::collect_garbage: failed to read a dir entry in '{}': {}",
+                    MODULE,
+                    work_dir.display(),
+                    why
+                ),
+            )
+        })?;
+
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if root_set.contains(&canonical) {
+            continue;
+        }
+
+        let modified = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let hash = sha256_hex(&path)?;
+
+        artifacts.push(Artifact { path, modified, hash });
+    }
+
+    artifacts.sort_by(|a, b| b.modified.cmp(&a.modified));
+
+    let mut seen_hashes = HashSet::new();
+    let mut kept = 0usize;
+    let mut pruned = Vec::new();
+
+    for artifact in artifacts {
+        let is_duplicate = !seen_hashes.insert(artifact.hash.clone());
+        let keep = !is_duplicate && retain.map_or(true, |limit| kept < limit);
+
+        if keep {
+            kept += 1;
+            continue;
+        }
+
+        if pretend {
+            info!(
+                "pretend mode: would remove stale work_dir artifact '{}'",
+                artifact.path.display()
+            );
+        } else {
+            remove_file(&artifact.path).map_err(|why| {
+                MigError::from_remark(
+                    MigErrorKind::Upstream,
+                    &format!(
+                        "{}::collect_garbage: failed to remove '{}': {}",
+                        MODULE,
+                        artifact.path.display(),
+                        why
+                    ),
+                )
+            })?;
+            info!("removed stale work_dir artifact '{}'", artifact.path.display());
+        }
+
+        pruned.push(artifact.path);
+    }
+
+    Ok(pruned)
+}
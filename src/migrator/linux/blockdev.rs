@@ -0,0 +1,164 @@
+// Structured block-device discovery.
+//
+// Instead of scraping the free-text output of `df`/`mount`, ask `lsblk` for a
+// JSON device tree (`-J`) with the extra columns we need (`-O`) and deserialize
+// it directly into `BlockDevice`/`Partition`, giving us a typed model of disks
+// and their partitions instead of regex soup.
+
+use log::{debug, trace};
+use serde::Deserialize;
+use std::path::Path;
+
+use super::util::call_cmd;
+use crate::migrator::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "linux::blockdev";
+const LSBLK_CMD: &str = "lsblk";
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Partition {
+    pub name: String,
+    #[serde(rename = "fstype")]
+    pub fs_type: Option<String>,
+    pub size: Option<String>,
+    pub mountpoint: Option<String>,
+    #[serde(default)]
+    pub children: Vec<Partition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct BlockDevice {
+    pub name: String,
+    #[serde(rename = "fstype")]
+    pub fs_type: Option<String>,
+    pub size: Option<String>,
+    pub mountpoint: Option<String>,
+    #[serde(default, rename = "children")]
+    pub partitions: Vec<Partition>,
+}
+
+impl BlockDevice {
+    /// Device path for this block device, e.g. `/dev/sda`.
+    pub fn device_path(&self) -> String {
+        format!("/dev/{}", self.name)
+    }
+
+    /// Find the partition (recursively) that is mounted at `mountpoint`.
+    pub fn find_partition_by_mountpoint(&self, mountpoint: &Path) -> Option<&Partition> {
+        fn search<'a>(partitions: &'a [Partition], mountpoint: &Path) -> Option<&'a Partition> {
+            for part in partitions {
+                if let Some(mp) = &part.mountpoint {
+                    if Path::new(mp) == mountpoint {
+                        return Some(part);
+                    }
+                }
+                if let Some(found) = search(&part.children, mountpoint) {
+                    return Some(found);
+                }
+            }
+            None
+        }
+        search(&self.partitions, mountpoint)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LsblkOutput {
+    #[serde(rename = "blockdevices")]
+    block_devices: Vec<BlockDevice>,
+}
+
+/// Run `lsblk -J -O` and parse the resulting device tree.
+pub(crate) fn query_block_devices() -> Result<Vec<BlockDevice>, MigError> {
+    trace!("{}::query_block_devices: entered", MODULE);
+
+    let args: Vec<&str> = vec!["-J", "-O"];
+    let cmd_res = call_cmd(LSBLK_CMD, &args, true)?;
+
+    if !cmd_res.status.success() {
+        return Err(MigError::from_remark(
+            MigErrorKind::ExecProcess,
+            &format!("{}::query_block_devices: lsblk failed: {}", MODULE, cmd_res.stderr),
+        ));
+    }
+
+    let parsed: LsblkOutput = serde_json::from_str(&cmd_res.stdout).context(MigErrCtx::from_remark(
+        MigErrorKind::InvParam,
+        &format!("{}::query_block_devices: failed to parse lsblk JSON output", MODULE),
+    ))?;
+
+    debug!(
+        "{}::query_block_devices: found {} block devices",
+        MODULE,
+        parsed.block_devices.len()
+    );
+
+    Ok(parsed.block_devices)
+}
+
+/// Known-unsupported source layouts that should abort a migration rather than
+/// risk flashing the wrong disk or silently skipping data.
+#[derive(Debug)]
+pub(crate) enum UnsupportedLayout {
+    Lvm,
+    Zfs,
+    Iscsi,
+}
+
+impl UnsupportedLayout {
+    pub fn describe(&self) -> &str {
+        match self {
+            UnsupportedLayout::Lvm => "source root sits on an LVM physical volume",
+            UnsupportedLayout::Zfs => "source root sits on a ZFS member device",
+            UnsupportedLayout::Iscsi => "source root sits on an iSCSI-backed device",
+        }
+    }
+}
+
+/// Detect a handful of source-disk layouts that `migrate` does not know how to
+/// safely handle, returning the reason if `part` or any of its children use
+/// one.
+pub(crate) fn check_unsupported_layout(
+    part: &Partition,
+    device_path: &str,
+) -> Option<UnsupportedLayout> {
+    match part.fs_type.as_deref() {
+        Some("LVM2_member") => return Some(UnsupportedLayout::Lvm),
+        Some("zfs_member") => return Some(UnsupportedLayout::Zfs),
+        _ => (),
+    }
+
+    lazy_static::lazy_static! {
+        static ref ISCSI_RE: regex::Regex = regex::Regex::new(r"host\d+/session\d+").unwrap();
+    }
+
+    // iSCSI-backed devices (and their partitions) show up under sysfs as
+    // `/sys/class/block/<dev>` symlinked into `.../hostN/sessionM/...`, not
+    // in the device path itself, so fully resolve that symlink rather than
+    // matching against `device_path`.
+    if let Ok(target) = std::fs::canonicalize(format!("/sys/class/block/{}", part.name)) {
+        if ISCSI_RE.is_match(&target.to_string_lossy()) {
+            return Some(UnsupportedLayout::Iscsi);
+        }
+    }
+
+    for child in &part.children {
+        if let Some(unsupported) = check_unsupported_layout(child, device_path) {
+            return Some(unsupported);
+        }
+    }
+
+    None
+}
+
+/// Resolve the `BlockDevice` backing a given mountpoint (e.g. the work dir or
+/// the source root), or `None` if no device in `devices` claims it.
+pub(crate) fn resolve_device_for_mountpoint<'a>(
+    devices: &'a [BlockDevice],
+    mountpoint: &Path,
+) -> Option<&'a BlockDevice> {
+    devices.iter().find(|dev| {
+        dev.mountpoint.as_deref().map(Path::new) == Some(mountpoint)
+            || dev.find_partition_by_mountpoint(mountpoint).is_some()
+    })
+}
@@ -0,0 +1,360 @@
+// Read-only ext2/ext4 inspection, so PRETEND mode (and pre-flight checks in
+// general) can confirm the source root/boot partitions contain what migrate
+// expects without mounting them - useful in read-only live environments or
+// under a foreign kernel where mounting isn't an option.
+//
+// This is intentionally small: enough superblock/inode/directory handling to
+// resolve a path and read small files (direct block pointers, plus ext4
+// extent trees of depth 0, i.e. up to four extents stored in the inode),
+// not a general-purpose ext4 driver.
+
+use log::{debug, trace};
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MODULE: &str = "linux::fs_inspect";
+
+const EXT_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT_MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+
+#[derive(Debug)]
+pub(crate) enum FsInspectError {
+    NotFound(String),
+    NotADirectory(String),
+    IsDirectory(String),
+    InvalidPath(String),
+    UnsupportedOperation(String),
+    Io(String),
+}
+
+impl fmt::Display for FsInspectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsInspectError::NotFound(p) => write!(f, "not found: '{}'", p),
+            FsInspectError::NotADirectory(p) => write!(f, "not a directory: '{}'", p),
+            FsInspectError::IsDirectory(p) => write!(f, "is a directory: '{}'", p),
+            FsInspectError::InvalidPath(p) => write!(f, "invalid path: '{}'", p),
+            FsInspectError::UnsupportedOperation(msg) => write!(f, "unsupported: {}", msg),
+            FsInspectError::Io(msg) => write!(f, "io error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FsInspectError {}
+
+struct Superblock {
+    inodes_count: u32,
+    blocks_count: u32,
+    log_block_size: u32,
+    inodes_per_group: u32,
+    inode_size: u16,
+    feature_incompat: u32,
+}
+
+impl Superblock {
+    fn block_size(&self) -> u64 {
+        1024 << self.log_block_size
+    }
+
+    fn groups_count(&self) -> u32 {
+        (self.inodes_count + self.inodes_per_group - 1) / self.inodes_per_group
+    }
+
+    fn supports_extents(&self) -> bool {
+        self.feature_incompat & INCOMPAT_EXTENTS != 0
+    }
+}
+
+/// A read-only handle onto an ext2/ext3/ext4 filesystem, opened from either a
+/// block device path or a byte offset into a disk image.
+pub(crate) struct ExtFs {
+    file: File,
+    base_offset: u64,
+    sb: Superblock,
+}
+
+impl ExtFs {
+    /// Open the filesystem starting at `offset` bytes into `device_or_image`
+    /// (0 for a dedicated partition device/image, or the partition's start
+    /// offset when working against a whole-disk image).
+    pub fn open<P: AsRef<Path>>(device_or_image: P, offset: u64) -> Result<ExtFs, FsInspectError> {
+        let mut file = File::open(device_or_image.as_ref())
+            .map_err(|e| FsInspectError::Io(format!("failed to open filesystem image: {}", e)))?;
+
+        let sb = Self::read_superblock(&mut file, offset)?;
+
+        Ok(ExtFs {
+            file,
+            base_offset: offset,
+            sb,
+        })
+    }
+
+    fn read_superblock(file: &mut File, offset: u64) -> Result<Superblock, FsInspectError> {
+        file.seek(SeekFrom::Start(offset + EXT_SUPERBLOCK_OFFSET))
+            .map_err(|e| FsInspectError::Io(format!("failed to seek to superblock: {}", e)))?;
+
+        let mut raw = [0u8; 264];
+        file.read_exact(&mut raw)
+            .map_err(|e| FsInspectError::Io(format!("failed to read superblock: {}", e)))?;
+
+        let magic = u16::from_le_bytes([raw[56], raw[57]]);
+        if magic != EXT_MAGIC {
+            return Err(FsInspectError::InvalidPath(String::from(
+                "not an ext2/ext3/ext4 filesystem (bad superblock magic)",
+            )));
+        }
+
+        Ok(Superblock {
+            inodes_count: u32::from_le_bytes(raw[0..4].try_into().unwrap()),
+            blocks_count: u32::from_le_bytes(raw[4..8].try_into().unwrap()),
+            log_block_size: u32::from_le_bytes(raw[24..28].try_into().unwrap()),
+            inodes_per_group: u32::from_le_bytes(raw[40..44].try_into().unwrap()),
+            inode_size: u16::from_le_bytes([raw[88], raw[89]]),
+            feature_incompat: u32::from_le_bytes(raw[96..100].try_into().unwrap()),
+        })
+    }
+
+    fn group_desc_table_offset(&self) -> u64 {
+        self.base_offset + self.sb.block_size() * if self.sb.block_size() == 1024 { 2 } else { 1 }
+    }
+
+    fn inode_location(&mut self, inode_num: u32) -> Result<(u32, u64), FsInspectError> {
+        let group = (inode_num - 1) / self.sb.inodes_per_group;
+        let index = (inode_num - 1) % self.sb.inodes_per_group;
+
+        // 32-byte group descriptors: bg_inode_table is a u32 at offset 8
+        let gd_offset = self.group_desc_table_offset() + u64::from(group) * 32;
+        self.file
+            .seek(SeekFrom::Start(gd_offset + 8))
+            .map_err(|e| FsInspectError::Io(format!("failed to seek to group descriptor: {}", e)))?;
+
+        let mut buf = [0u8; 4];
+        self.file
+            .read_exact(&mut buf)
+            .map_err(|e| FsInspectError::Io(format!("failed to read group descriptor: {}", e)))?;
+        let inode_table_block = u32::from_le_bytes(buf);
+
+        let inode_offset = self.base_offset
+            + u64::from(inode_table_block) * self.sb.block_size()
+            + u64::from(index) * u64::from(self.sb.inode_size);
+
+        Ok((group, inode_offset))
+    }
+
+    fn read_inode(&mut self, inode_num: u32) -> Result<Inode, FsInspectError> {
+        let (_group, offset) = self.inode_location(inode_num)?;
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .map_err(|e| FsInspectError::Io(format!("failed to seek to inode: {}", e)))?;
+
+        let mut raw = [0u8; 160];
+        self.file
+            .read_exact(&mut raw)
+            .map_err(|e| FsInspectError::Io(format!("failed to read inode {}: {}", inode_num, e)))?;
+
+        let mode = u16::from_le_bytes([raw[0], raw[1]]);
+        let size = u32::from_le_bytes(raw[4..8].try_into().unwrap());
+        let mut block_raw = [0u8; 60];
+        block_raw.copy_from_slice(&raw[40..100]);
+
+        Ok(Inode {
+            mode,
+            size: u64::from(size),
+            block: block_raw,
+        })
+    }
+
+    /// Resolve `path` (e.g. `/etc/os-release`) to its inode, walking
+    /// directory entries from the root inode.
+    fn resolve(&mut self, path: &str) -> Result<(u32, Inode), FsInspectError> {
+        if !path.starts_with('/') {
+            return Err(FsInspectError::InvalidPath(path.to_string()));
+        }
+
+        let mut inode_num = ROOT_INODE;
+        let mut inode = self.read_inode(inode_num)?;
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !inode.is_dir() {
+                return Err(FsInspectError::NotADirectory(path.to_string()));
+            }
+
+            let entries = self.read_dir_entries(&inode)?;
+            let found = entries.into_iter().find(|(name, _)| name == component);
+
+            match found {
+                Some((_, child_inode_num)) => {
+                    inode_num = child_inode_num;
+                    inode = self.read_inode(inode_num)?;
+                }
+                None => return Err(FsInspectError::NotFound(path.to_string())),
+            }
+        }
+
+        Ok((inode_num, inode))
+    }
+
+    fn read_dir_entries(&mut self, inode: &Inode) -> Result<Vec<(String, u32)>, FsInspectError> {
+        let data = self.read_inode_data(inode)?;
+        let mut entries = Vec::new();
+        let mut pos = 0usize;
+
+        while pos + 8 <= data.len() {
+            let entry_inode = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+            let rec_len = u16::from_le_bytes(data[pos + 4..pos + 6].try_into().unwrap()) as usize;
+            let name_len = data[pos + 6] as usize;
+
+            if rec_len == 0 {
+                break;
+            }
+
+            if entry_inode != 0 && pos + 8 + name_len <= data.len() {
+                let name = String::from_utf8_lossy(&data[pos + 8..pos + 8 + name_len]).to_string();
+                if name != "." && name != ".." {
+                    entries.push((name, entry_inode));
+                }
+            }
+
+            pos += rec_len;
+        }
+
+        Ok(entries)
+    }
+
+    /// Read the full contents of `inode`, following direct block pointers
+    /// (ext2-style) or, if the filesystem uses extents, the up to four
+    /// extents stored inline in the inode - indirect blocks and extent trees
+    /// of depth > 0 are not supported and return `UnsupportedOperation`.
+    fn read_inode_data(&mut self, inode: &Inode) -> Result<Vec<u8>, FsInspectError> {
+        let block_size = self.sb.block_size();
+        let mut data = Vec::with_capacity(inode.size as usize);
+
+        if self.sb.supports_extents() && inode.uses_extents() {
+            for (block, len) in inode.extent_blocks()? {
+                for i in 0..len {
+                    data.extend_from_slice(&self.read_block(block + u64::from(i))?);
+                }
+            }
+        } else {
+            for &block in inode.direct_blocks().iter() {
+                if block == 0 {
+                    break;
+                }
+                data.extend_from_slice(&self.read_block(u64::from(block))?);
+            }
+        }
+
+        data.truncate(inode.size as usize);
+        if data.len() < inode.size as usize {
+            return Err(FsInspectError::UnsupportedOperation(String::from(
+                "file data is not fully covered by direct blocks or inline extents, which is not supported",
+            )));
+        }
+
+        let _ = block_size;
+        Ok(data)
+    }
+
+    fn read_block(&mut self, block_num: u64) -> Result<Vec<u8>, FsInspectError> {
+        let block_size = self.sb.block_size();
+        self.file
+            .seek(SeekFrom::Start(self.base_offset + block_num * block_size))
+            .map_err(|e| FsInspectError::Io(format!("failed to seek to block {}: {}", block_num, e)))?;
+
+        let mut buf = vec![0u8; block_size as usize];
+        self.file
+            .read_exact(&mut buf)
+            .map_err(|e| FsInspectError::Io(format!("failed to read block {}: {}", block_num, e)))?;
+        Ok(buf)
+    }
+
+    /// Read the full contents of the regular file at `path`.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsInspectError> {
+        let (_inode_num, inode) = self.resolve(path)?;
+        if inode.is_dir() {
+            return Err(FsInspectError::IsDirectory(path.to_string()));
+        }
+        trace!("{}::read_file: '{}' is {} bytes", MODULE, path, inode.size);
+        self.read_inode_data(&inode)
+    }
+
+    /// True if `path` exists and is not a directory (symlinks are not
+    /// followed, so a symlink counts as existing).
+    pub fn file_exists(&mut self, path: &str) -> bool {
+        match self.resolve(path) {
+            Ok((_, inode)) => !inode.is_dir(),
+            Err(_) => false,
+        }
+    }
+
+    /// List entry names of the directory at `path`.
+    pub fn list_dir(&mut self, path: &str) -> Result<Vec<String>, FsInspectError> {
+        let (_inode_num, inode) = self.resolve(path)?;
+        if !inode.is_dir() {
+            return Err(FsInspectError::NotADirectory(path.to_string()));
+        }
+        Ok(self
+            .read_dir_entries(&inode)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+}
+
+struct Inode {
+    mode: u16,
+    size: u64,
+    block: [u8; 60],
+}
+
+const S_IFDIR: u16 = 0x4000;
+const S_IFMT: u16 = 0xF000;
+
+impl Inode {
+    fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    fn direct_blocks(&self) -> [u32; 12] {
+        let mut blocks = [0u32; 12];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = u32::from_le_bytes(self.block[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        blocks
+    }
+
+    fn uses_extents(&self) -> bool {
+        u16::from_le_bytes([self.block[0], self.block[1]]) == 0xF30A
+    }
+
+    /// The extent tree's root stores up to 4 extents inline; we only support
+    /// reading those (no depth > 0 trees).
+    fn extent_blocks(&self) -> Result<Vec<(u64, u32)>, FsInspectError> {
+        let entries = u16::from_le_bytes([self.block[2], self.block[3]]);
+        let depth = u16::from_le_bytes([self.block[6], self.block[7]]);
+        if depth != 0 {
+            return Err(FsInspectError::UnsupportedOperation(String::from(
+                "extent trees with depth > 0 are not supported",
+            )));
+        }
+
+        let mut result = Vec::new();
+        for i in 0..entries as usize {
+            let base = 12 + i * 12;
+            if base + 12 > self.block.len() {
+                break;
+            }
+            let len = u16::from_le_bytes(self.block[base + 4..base + 6].try_into().unwrap()) as u32;
+            let start_hi = u16::from_le_bytes(self.block[base + 6..base + 8].try_into().unwrap()) as u64;
+            let start_lo = u32::from_le_bytes(self.block[base + 8..base + 12].try_into().unwrap()) as u64;
+            result.push(((start_hi << 32) | start_lo, len));
+        }
+        debug!("{}::extent_blocks: {:?}", MODULE, result);
+        Ok(result)
+    }
+}
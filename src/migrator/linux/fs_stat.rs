@@ -0,0 +1,80 @@
+// Native filesystem/mount-state probing via the `nix` crate's `statvfs`
+// bindings, used in place of shelling out to `df`/`mount` and scraping
+// locale-dependent text output.
+
+use log::debug;
+use nix::sys::statvfs::statvfs;
+use std::path::Path;
+
+use crate::migrator::{MigError, MigErrorKind};
+
+const MODULE: &str = "linux::fs_stat";
+
+/// Disk-space facts for a mounted filesystem, obtained directly via
+/// `statvfs(2)` rather than parsed `df` output.
+#[derive(Debug)]
+pub(crate) struct FsStat {
+    pub block_size: u64,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// `statvfs(path)` the filesystem `path` resides on and return typed
+/// size/free-space figures.
+pub(crate) fn fs_stat<P: AsRef<Path>>(path: P) -> Result<FsStat, MigError> {
+    let path = path.as_ref();
+
+    let stat = statvfs(path).map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::fs_stat: statvfs failed for '{}': {}", MODULE, path.display(), why),
+        )
+    })?;
+
+    let block_size = stat.fragment_size() as u64;
+    let fs_stat = FsStat {
+        block_size,
+        total_bytes: stat.blocks() as u64 * block_size,
+        free_bytes: stat.blocks_free() as u64 * block_size,
+        available_bytes: stat.blocks_available() as u64 * block_size,
+    };
+
+    debug!("{}::fs_stat: '{}' -> {:?}", MODULE, path.display(), fs_stat);
+
+    Ok(fs_stat)
+}
+
+/// Whether `path` is itself a mount point, determined by comparing the
+/// device id of `path` and its parent directory - no `mount`/`/proc/mounts`
+/// parsing required.
+pub(crate) fn is_mountpoint<P: AsRef<Path>>(path: P) -> Result<bool, MigError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = path.as_ref();
+    let meta = path.metadata().map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!("{}::is_mountpoint: failed to stat '{}': {}", MODULE, path.display(), why),
+        )
+    })?;
+
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return Ok(true), // '/' has no parent, is always a mountpoint
+    };
+
+    let parent_meta = parent.metadata().map_err(|why| {
+        MigError::from_remark(
+            MigErrorKind::Upstream,
+            &format!(
+                "{}::is_mountpoint: failed to stat parent of '{}': {}",
+                MODULE,
+                path.display(),
+                why
+            ),
+        )
+    })?;
+
+    Ok(meta.dev() != parent_meta.dev())
+}
@@ -0,0 +1,168 @@
+// Pre-flight SMART health gate: flashing balenaOS onto a dying eMMC/SD/SSD
+// wastes a migration window and can brick a device mid-write, so before we
+// write to the target device we ask `smartctl` how healthy it thinks it is.
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use super::util::{call_cmd, SMARTCTL_CMD};
+use crate::migrator::{MigErrCtx, MigError, MigErrorKind, ResultExt};
+
+const MODULE: &str = "linux::smart";
+
+// thresholds chosen to be conservative - a single reallocated sector does not
+// necessarily mean imminent failure, but a grown count of them does
+const MAX_REALLOCATED_SECTORS: u64 = 8;
+const MAX_PENDING_SECTORS: u64 = 1;
+const MAX_PERCENTAGE_USED: u64 = 90;
+
+#[derive(Debug, Deserialize)]
+struct SmartctlOutput {
+    #[serde(default)]
+    smart_status: Option<SmartStatus>,
+    #[serde(default)]
+    ata_smart_attributes: Option<AtaSmartAttributes>,
+    #[serde(default)]
+    nvme_smart_health_information_log: Option<NvmeSmartLog>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SmartStatus {
+    passed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtaSmartAttributes {
+    table: Vec<AtaSmartAttribute>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtaSmartAttribute {
+    id: u32,
+    name: String,
+    raw: AtaSmartAttributeRaw,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtaSmartAttributeRaw {
+    value: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct NvmeSmartLog {
+    #[serde(default)]
+    percentage_used: Option<u64>,
+    #[serde(default)]
+    media_errors: Option<u64>,
+}
+
+/// Outcome of a SMART pre-flight check on the target flash device.
+#[derive(Debug)]
+pub(crate) struct SmartVerdict {
+    pub healthy: bool,
+    pub reasons: Vec<String>,
+}
+
+const ATTR_REALLOCATED_SECTOR_CT: u32 = 5;
+const ATTR_CURRENT_PENDING_SECTOR: u32 = 197;
+
+/// Run `smartctl -H -A -j <device>` and evaluate the overall health verdict
+/// plus a handful of critical attributes.
+pub(crate) fn check_device_health(device: &str) -> Result<SmartVerdict, MigError> {
+    let args: Vec<&str> = vec!["-H", "-A", "-j", device];
+    let cmd_res = call_cmd(SMARTCTL_CMD, &args, true)?;
+
+    // smartctl uses its exit code as a bitmask of warnings, not a pass/fail
+    // flag, so we only treat a completely unparsable response as an error
+    let parsed: SmartctlOutput = serde_json::from_str(&cmd_res.stdout).context(MigErrCtx::from_remark(
+        MigErrorKind::InvParam,
+        &format!(
+            "{}::check_device_health: failed to parse smartctl JSON output for '{}'",
+            MODULE, device
+        ),
+    ))?;
+
+    let mut reasons: Vec<String> = Vec::new();
+
+    if let Some(status) = &parsed.smart_status {
+        if !status.passed {
+            reasons.push(String::from("overall SMART health assessment: FAILED"));
+        }
+    }
+
+    if let Some(attrs) = &parsed.ata_smart_attributes {
+        for attr in &attrs.table {
+            match attr.id {
+                ATTR_REALLOCATED_SECTOR_CT if attr.raw.value > MAX_REALLOCATED_SECTORS => {
+                    reasons.push(format!(
+                        "{} ({}): {} exceeds threshold {}",
+                        attr.name, attr.id, attr.raw.value, MAX_REALLOCATED_SECTORS
+                    ));
+                }
+                ATTR_CURRENT_PENDING_SECTOR if attr.raw.value > MAX_PENDING_SECTORS => {
+                    reasons.push(format!(
+                        "{} ({}): {} exceeds threshold {}",
+                        attr.name, attr.id, attr.raw.value, MAX_PENDING_SECTORS
+                    ));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if let Some(nvme) = &parsed.nvme_smart_health_information_log {
+        if let Some(used) = nvme.percentage_used {
+            if used > MAX_PERCENTAGE_USED {
+                reasons.push(format!(
+                    "percentage_used: {} exceeds threshold {}",
+                    used, MAX_PERCENTAGE_USED
+                ));
+            }
+        }
+        if let Some(errors) = nvme.media_errors {
+            if errors > 0 {
+                reasons.push(format!("media_errors: {}", errors));
+            }
+        }
+    }
+
+    let healthy = reasons.is_empty();
+    debug!(
+        "{}::check_device_health: '{}' healthy: {}, reasons: {:?}",
+        MODULE, device, healthy, reasons
+    );
+
+    Ok(SmartVerdict { healthy, reasons })
+}
+
+/// Evaluate `device` against the configured SMART gate, aborting (or just
+/// warning, if `force` is set) when the disk reports failing or near-end-of-life.
+pub(crate) fn smart_preflight(device: &str, force: bool) -> Result<(), MigError> {
+    let verdict = match check_device_health(device) {
+        Ok(verdict) => verdict,
+        Err(why) => {
+            warn!(
+                "{}::smart_preflight: could not determine SMART health of '{}': {:?}",
+                MODULE, device, why
+            );
+            return Ok(());
+        }
+    };
+
+    if verdict.healthy {
+        return Ok(());
+    }
+
+    let message = format!(
+        "target device '{}' failed SMART pre-flight check: {}",
+        device,
+        verdict.reasons.join("; ")
+    );
+
+    if force {
+        warn!("{}::smart_preflight: {} (continuing, force flag set)", MODULE, message);
+        Ok(())
+    } else {
+        Err(MigError::from_remark(MigErrorKind::InvState, &message))
+    }
+}
@@ -1,17 +1,13 @@
-use failure::{ResultExt};
 use log::debug;
 use regex::Regex;
 use std::fs::read_to_string;
 // use std::io::Read;
 use std::path::Path;
-use log::{trace, error};
-use lazy_static::lazy_static;
-use std::collections::HashMap;
+use log::trace;
 
 // use libc::{getuid, sysinfo};
 
 const MODULE: &str = "Linux::util";
-const WHEREIS_CMD: &str = "whereis";
 
 pub const DF_CMD: &str = "df";
 pub const LSBLK_CMD: &str = "lsblk";
@@ -20,73 +16,33 @@ pub const FILE_CMD: &str = "file";
 pub const UNAME_CMD: &str = "uname";
 pub const MOKUTIL_CMD: &str = "mokutil";
 pub const GRUB_INSTALL_CMD: &str = "grub-install";
-
-
-const REQUIRED_CMDS: &'static [&'static str] = &[
-    DF_CMD, 
-    LSBLK_CMD, 
-    MOUNT_CMD,
-    FILE_CMD,
-    UNAME_CMD,
-]; 
-
-const OPTIONAL_CMDS: &'static [&'static str] = &[
-    MOKUTIL_CMD,
-    GRUB_INSTALL_CMD,
-]; 
+pub const SMARTCTL_CMD: &str = "smartctl";
 
 
 use crate::migrator::{
     linux::LinuxMigrator,
-    common::{call, CmdRes},
-    MigErrCtx, 
-    MigError, 
+    common::{call, cmd_resolver, CmdRes},
+    MigErrCtx,
+    MigError,
     MigErrorKind,
+    ResultExt,
     };
 
+use super::blockdev::{check_unsupported_layout, query_block_devices, resolve_device_for_mountpoint};
+use std::path::PathBuf;
 
+
+/// Resolve `cmd` to an absolute path via `cmd_resolver` (cached PATH
+/// lookup) and run it - so a missing binary is reported with the tool name
+/// and the directories searched, rather than failing deep inside `call()`.
 pub(crate) fn call_cmd(
     cmd: &str,
     args: &[&str],
     trim_stdout: bool,
 ) -> Result<CmdRes, MigError> {
-    lazy_static! {
-        static ref CMD_PATH: HashMap<String,Option<String>> = {
-            let mut map = HashMap::new();
-            for cmd in REQUIRED_CMDS {
-                map.insert(
-                    String::from(*cmd), 
-                    Some(match whereis(cmd) {
-                        Ok(cmd) => cmd,
-                        Err(why) => {
-                            let message = format!("cannot find required command {}", cmd);
-                            error!("{}", message);
-                            panic!("{}", message);
-                        }
-                    }));      
-            }
-            for cmd in OPTIONAL_CMDS {
-                map.insert(
-                    String::from(*cmd), 
-                    match whereis(cmd) {
-                        Ok(cmd) => Some(cmd),
-                        Err(_why) => None, // TODO: check error codes
-                    });      
-            }
-            map
-        };
-    }
-
     trace!("call_cmd: entered with cmd: '{}', args: {:?}, trim: {}", cmd, args, trim_stdout);
-    if let Some(found_cmd) = CMD_PATH.get(cmd) {
-        if let Some(valid_cmd) = found_cmd {
-            Ok(call(valid_cmd, args, trim_stdout)?) 
-        } else {
-            Err(MigError::from_remark(MigErrorKind::NotFound,&format!("{}::call_cmd: {} is not available", MODULE, cmd)))    
-        }
-    } else {
-        Err(MigError::from_remark(MigErrorKind::InvParam,&format!("{}::call_cmd: {} is not in the list of checked commands", MODULE, cmd)))
-    }
+    let resolved = cmd_resolver::resolve(cmd)?;
+    call(&resolved.to_string_lossy(), args, trim_stdout)
 }
 
 
@@ -115,8 +71,13 @@ pub(crate) fn check_work_file(file: &str, work_dir: &str ) -> Result<Option<(Str
     debug!("{}::check_work_file: checked path for '{}': '{:?}'", MODULE, file, &checked_path);
 
     if let Some(path) = checked_path {
+        if is_qcow2_file(&path)? {
+            debug!("{}::check_work_file: '{}' is a qcow2 image", MODULE, path);
+            return Ok(Some((path, String::from("qcow2 image"))));
+        }
+
         let args: Vec<&str> =  vec!["-b", "-i", &path];
-        let cmd_res = call_cmd("file", &args , true)?;       
+        let cmd_res = call_cmd("file", &args , true)?;
         if !cmd_res.status.success() || cmd_res.stdout.is_empty() {
             return Err(MigError::from_remark(MigErrorKind::InvParam , &format!("{}::new: failed determine type for file {}", MODULE, path)));
         }
@@ -127,6 +88,21 @@ pub(crate) fn check_work_file(file: &str, work_dir: &str ) -> Result<Option<(Str
 
 }
 
+/// Peek at the file's magic bytes to see if it's a qcow2 image, so the flash
+/// path can stream sectors from it directly instead of requiring a prior
+/// `qemu-img convert` to raw.
+fn is_qcow2_file(path: &str) -> Result<bool, MigError> {
+    use crate::migrator::common::qcow::is_qcow2;
+    use std::fs::File;
+
+    let mut file = File::open(path).context(MigErrCtx::from_remark(
+        MigErrorKind::Upstream,
+        &format!("{}::is_qcow2_file: failed to open '{}'", MODULE, path),
+    ))?;
+
+    is_qcow2(&mut file)
+}
+
 
 pub fn parse_file(fname: &str, regex: &Regex) -> Result<Option<String>, MigError> {
     let os_info = read_to_string(fname).context(MigErrCtx::from_remark(
@@ -162,45 +138,55 @@ pub fn file_exists(file: &str) -> bool {
     Path::new(file).exists()    
 }
 
-pub fn whereis(cmd: &str) -> Result<String, MigError> {
-    let args: [&str; 2] = ["-b", cmd];
-    let cmd_res = call(WHEREIS_CMD, &args, true).context(MigErrCtx::from_remark(
-        MigErrorKind::Upstream,
-        &format!("{}::whereis: failed for '{}'", MODULE, cmd),
-    ))?;
-    if cmd_res.status.success() {
-        if cmd_res.stdout.is_empty() {
-            Err(MigError::from_remark(
-                MigErrorKind::InvParam,
-                &format!("{}::whereis: no command output for {}", MODULE, cmd),
-            ))
-        } else {
-            let mut words = cmd_res.stdout.split(" ");
-            if let Some(s) = words.nth(1) {
-                Ok(String::from(s))
-            } else {
-                Err(MigError::from_remark(
-                    MigErrorKind::NotFound,
-                    &format!("{}::whereis: command not found: '{}'", MODULE, cmd),
-                ))
-                //
-            }
-        }
-    } else {
-        Err(MigError::from_remark(
-            MigErrorKind::ExecProcess,
+
+/// Resolve the block device backing `mountpoint` (e.g. the work dir or the
+/// source root) and refuse to continue if it sits on a layout we can't safely
+/// migrate from (LVM, ZFS, iSCSI) instead of silently flashing the wrong disk.
+pub(crate) fn check_source_layout(mountpoint: &str) -> Result<(), MigError> {
+    let devices = query_block_devices()?;
+    let mountpoint = PathBuf::from(mountpoint);
+
+    let device = resolve_device_for_mountpoint(&devices, &mountpoint).ok_or_else(|| {
+        MigError::from_remark(
+            MigErrorKind::NotFound,
             &format!(
-                "{}::whereis: command failed for {}: {}",
+                "{}::check_source_layout: could not resolve a block device for '{}'",
                 MODULE,
-                cmd,
-                cmd_res.status.code().unwrap_or(0)
+                mountpoint.display()
             ),
-        ))
+        )
+    })?;
+
+    for part in &device.partitions {
+        if let Some(unsupported) = check_unsupported_layout(part, &device.device_path()) {
+            return Err(MigError::from_remark(
+                MigErrorKind::InvState,
+                &format!(
+                    "{}::check_source_layout: unsupported source layout on '{}': {}",
+                    MODULE,
+                    device.device_path(),
+                    unsupported.describe()
+                ),
+            ));
+        }
     }
+
+    debug!(
+        "{}::check_source_layout: '{}' is backed by supported device '{}'",
+        MODULE,
+        mountpoint.display(),
+        device.device_path()
+    );
+
+    Ok(())
 }
 
 pub fn command_exists(cmd: &str) -> Result<bool, MigError> {
-    Err(MigError::from(MigErrorKind::NotImpl))
+    match cmd_resolver::resolve(cmd) {
+        Ok(_) => Ok(true),
+        Err(why) if why.kind() == MigErrorKind::NotFound => Ok(false),
+        Err(why) => Err(why),
+    }
 }
 
 pub fn exec_command(cmd: &str) -> Result<bool, MigError> {